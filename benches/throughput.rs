@@ -0,0 +1,43 @@
+//! Throughput of the terminal on programs that output a lot, from the bytes read from the pty to
+//! the frames drawn.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+use termcast::backends::TermionBackend;
+use termcast::layout::Rect;
+use termcast::terminal::Terminal;
+
+/// Size of the reads from the pty.
+const CHUNK: usize = 64 * 1024;
+
+/// Feeds `output` to an 80x24 cast, drawing after every read.
+fn cast(output: &[u8]) {
+    let backend = TermionBackend::new(std::io::sink());
+    let mut terminal = Terminal::new(Rect::new(0, 0, 80, 24), backend);
+    let mut parser = vte::Parser::new();
+    for chunk in output.chunks(CHUNK) {
+        for &byte in chunk {
+            parser.advance(&mut terminal, byte);
+        }
+        futures::executor::block_on(terminal.draw()).unwrap();
+    }
+}
+
+fn throughput(c: &mut Criterion) {
+    // the pty turns the newlines into `\r\n`.
+    let yes = b"y\r\n".repeat(1 << 20);
+    let seq: Vec<u8> = (1..=1_000_000)
+        .flat_map(|i| format!("{}\r\n", i).into_bytes())
+        .collect();
+
+    let mut group = c.benchmark_group("throughput");
+    group.sample_size(10);
+    for (name, output) in [("yes", &yes), ("seq", &seq)] {
+        group.throughput(Throughput::Bytes(output.len() as u64));
+        group.bench_function(name, |b| b.iter(|| cast(output)));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, throughput);
+criterion_main!(benches);
@@ -0,0 +1,176 @@
+//! Cost of sending the frames to many viewers, each connected over the loopback like a remote
+//! one. A frame is encoded once and the same buffer is written to every viewer, so the
+//! allocations per frame should not grow with the number of viewers.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use hmac::{Hmac, Mac, NewMac};
+use sha2::Sha256;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::Runtime;
+use tokio::sync::{broadcast, mpsc};
+
+use termcast::backends::TermionBackend;
+use termcast::layout::Rect;
+use termcast::network::access::Access;
+use termcast::network::auth::Auth;
+use termcast::network::protocol::Message;
+use termcast::network::{Network, NetworkEvent};
+use termcast::terminal::Terminal;
+
+/// Counts the allocations of the whole process, the client tasks included.
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const TOKEN: &str = "bench";
+/// Frames sent in each round, all of them fitting in the broadcast so that no viewer lags.
+const FRAMES: usize = 100;
+/// Tag of `Message::Hello`.
+const HELLO: u8 = 0;
+/// Tag of `Message::Frame`.
+const FRAME: u8 = 1;
+
+/// Returns the encoded frames of `seq` scrolling an 80x24 cast.
+fn frames() -> Vec<Arc<[u8]>> {
+    let backend = TermionBackend::new(std::io::sink());
+    let mut terminal = Terminal::new(Rect::new(0, 0, 80, 24), backend);
+    let mut parser = vte::Parser::new();
+    (0..FRAMES)
+        .map(|i| {
+            for byte in format!("{}\r\n", i).bytes() {
+                parser.advance(&mut terminal, byte);
+            }
+            let frame = futures::executor::block_on(terminal.draw()).unwrap();
+            Message::Frame(frame).encode().into()
+        })
+        .collect()
+}
+
+/// Connects a viewer, answers the challenge and waits for the hello, sent once it is admitted.
+async fn connect(addr: SocketAddr) -> TcpStream {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let len = stream.read_u32().await.unwrap() as usize;
+    let mut challenge = vec![0; len];
+    stream.read_exact(&mut challenge).await.unwrap();
+    let mut mac = Hmac::<Sha256>::new_from_slice(TOKEN.as_bytes()).unwrap();
+    mac.update(&challenge[1..]);
+    let mut auth = vec![0];
+    auth.extend_from_slice(&mac.finalize().into_bytes());
+    auth.extend_from_slice(b"viewer");
+    stream.write_u32(auth.len() as u32).await.unwrap();
+    stream.write_all(&auth).await.unwrap();
+    let len = stream.read_u32().await.unwrap() as usize;
+    let mut hello = vec![0; len];
+    stream.read_exact(&mut hello).await.unwrap();
+    assert_eq!(hello[0], HELLO);
+    stream
+}
+
+/// Reads the messages until `n` frames were received.
+async fn receive(stream: &mut TcpStream, n: usize) {
+    let mut received = 0;
+    let mut payload = Vec::new();
+    while received < n {
+        let len = stream.read_u32().await.unwrap() as usize;
+        payload.resize(len, 0);
+        stream.read_exact(&mut payload).await.unwrap();
+        if payload[0] == FRAME {
+            received += 1;
+        }
+    }
+}
+
+/// A cast with `n` viewers connected.
+struct Cast {
+    sender: broadcast::Sender<Arc<[u8]>>,
+    viewers: Vec<TcpStream>,
+    // the network stops when the host is gone.
+    _events: mpsc::UnboundedReceiver<NetworkEvent>,
+    _commands: mpsc::UnboundedSender<termcast::network::NetworkCommand>,
+}
+
+async fn cast(n: usize) -> Cast {
+    let (sender, _) = broadcast::channel(FRAMES);
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (events_sender, mut events) = mpsc::unbounded_channel();
+    let (commands, commands_receiver) = mpsc::unbounded_channel();
+    let access = Access::new(Auth::Tokens(vec![TOKEN.to_string()]), vec![], vec![], None);
+    let network = Network::new(
+        sender.clone(),
+        listener,
+        Rect::new(0, 0, 80, 24),
+        events_sender,
+        commands_receiver,
+        access,
+        None,
+    );
+    tokio::task::spawn(network.run());
+    let mut viewers = Vec::new();
+    for _ in 0..n {
+        viewers.push(connect(addr).await);
+    }
+    // the viewers are subscribed to the broadcast once the host is told about them.
+    let mut connected = 0;
+    while connected < n {
+        if let Some(NetworkEvent::Connected { .. }) = events.recv().await {
+            connected += 1;
+        }
+    }
+    Cast {
+        sender,
+        viewers,
+        _events: events,
+        _commands: commands,
+    }
+}
+
+/// Sends the frames to every viewer, and waits for all of them to have them.
+async fn round(cast: &mut Cast, frames: &[Arc<[u8]>]) {
+    for frame in frames {
+        cast.sender.send(frame.clone()).unwrap();
+    }
+    let receiving = cast.viewers.iter_mut().map(|viewer| receive(viewer, frames.len()));
+    futures::future::join_all(receiving).await;
+}
+
+fn viewers(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let frames = frames();
+    let mut group = c.benchmark_group("viewers");
+    group.sample_size(10);
+    for n in [1, 10, 50] {
+        let mut cast = runtime.block_on(cast(n));
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        runtime.block_on(round(&mut cast, &frames));
+        let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+        println!("{} viewers: {} allocations per frame", n, allocations / FRAMES);
+        group.bench_with_input(BenchmarkId::from_parameter(n), &n, |b, _| {
+            b.iter(|| runtime.block_on(round(&mut cast, &frames)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, viewers);
+criterion_main!(benches);
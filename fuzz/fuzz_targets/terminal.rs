@@ -0,0 +1,28 @@
+//! Feeds arbitrary program output to the terminal, which must neither panic nor lose track of
+//! its grid.
+
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use termcast::backends::TermionBackend;
+use termcast::layout::Rect;
+use termcast::terminal::Terminal;
+
+const WIDTH: usize = 20;
+const HEIGHT: usize = 8;
+
+fuzz_target!(|data: &[u8]| {
+    let backend = TermionBackend::new(std::io::sink());
+    let mut terminal = Terminal::new(Rect::new(0, 0, WIDTH, HEIGHT), backend);
+    let mut parser = vte::Parser::new();
+    // drawn twice, so that the scrolls and damage recorded in between are replayed too.
+    let (first, second) = data.split_at(data.len() / 2);
+    for part in [first, second] {
+        for &byte in part {
+            parser.advance(&mut terminal, byte);
+        }
+        let frame = futures::executor::block_on(terminal.draw()).unwrap();
+        assert!(frame.cells.iter().all(|&(x, y, _)| x < WIDTH && y < HEIGHT));
+    }
+    assert_eq!(terminal.keyframe().cells.len(), WIDTH * HEIGHT);
+});
@@ -4,6 +4,8 @@ use async_trait::async_trait;
 use tokio::io;
 
 use crate::cell::Cell;
+use crate::mode::Mode;
+use crate::palette::PaletteChange;
 
 pub use self::termion::TermionBackend;
 
@@ -13,6 +15,9 @@ pub trait Backend {
         where
             I: Iterator<Item = (usize, usize, Cell)> + Sync + Send;
 
+    /// Scrolls the screen rows `top..bottom` up by `n` rows, or down if `n` is negative.
+    async fn scroll(&mut self, top: usize, bottom: usize, n: isize) -> io::Result<()>;
+
     async fn clear(&mut self) -> io::Result<()>;
 
     async fn hide_cursor(&mut self) -> io::Result<()>;
@@ -21,5 +26,14 @@ pub trait Backend {
 
     async fn cursor_goto(&mut self, cols: usize, rows: usize) -> io::Result<()>;
 
+    /// Writes `content`, base64 encoded, to the `selection` clipboard with OSC 52.
+    async fn set_clipboard(&mut self, selection: &str, content: &str) -> io::Result<()>;
+
+    /// Changes a palette entry, with OSC 4/10/11/12, or resets it with OSC 104/110/111/112.
+    async fn set_palette(&mut self, change: PaletteChange) -> io::Result<()>;
+
+    /// Enables or disables the private modes in `mode`.
+    async fn set_mode(&mut self, mode: Mode, enable: bool) -> io::Result<()>;
+
     async fn flush(&mut self) -> io::Result<()>;
 }
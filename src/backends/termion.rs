@@ -6,6 +6,8 @@ use tokio::io;
 
 use super::Backend;
 use crate::cell::Cell;
+use crate::mode::Mode;
+use crate::palette::{PaletteChange, PaletteIndex};
 use crate::style;
 
 pub struct TermionBackend<W> {
@@ -39,6 +41,17 @@ impl<W: std::io::Write> Backend for TermionBackend<W> {
             Ok(())
     }
 
+    async fn scroll(&mut self, top: usize, bottom: usize, n: isize) -> io::Result<()> {
+        write!(self.buffer, "\x1b[{};{}r", top + 1, bottom).unwrap();
+        if n > 0 {
+            write!(self.buffer, "\x1b[{}S", n).unwrap();
+        } else {
+            write!(self.buffer, "\x1b[{}T", -n).unwrap();
+        }
+        write!(self.buffer, "\x1b[r").unwrap();
+        Ok(())
+    }
+
     async fn clear(&mut self) -> Result<(), io::Error> {
         write!(self.buffer, "{}", termion::clear::All).unwrap();
         Ok(())
@@ -59,8 +72,38 @@ impl<W: std::io::Write> Backend for TermionBackend<W> {
         Ok(())
     }
 
+    async fn set_clipboard(&mut self, selection: &str, content: &str) -> io::Result<()> {
+        write!(self.buffer, "\x1b]52;{};{}\x07", selection, content).unwrap();
+        Ok(())
+    }
+
+    async fn set_palette(&mut self, change: PaletteChange) -> io::Result<()> {
+        match (change.index, change.color) {
+            (PaletteIndex::Indexed(i), Some(color)) => {
+                write!(self.buffer, "\x1b]4;{};{}\x07", i, color).unwrap()
+            }
+            (PaletteIndex::Indexed(i), None) => write!(self.buffer, "\x1b]104;{}\x07", i).unwrap(),
+            (index, Some(color)) => {
+                write!(self.buffer, "\x1b]{};{}\x07", index.osc().unwrap_or_default(), color).unwrap()
+            }
+            (index, None) => {
+                let reset = 100 + u16::from(index.osc().unwrap_or_default());
+                write!(self.buffer, "\x1b]{}\x07", reset).unwrap()
+            }
+        }
+        Ok(())
+    }
+
+    async fn set_mode(&mut self, mode: Mode, enable: bool) -> io::Result<()> {
+        let action = if enable { 'h' } else { 'l' };
+        for number in mode.numbers() {
+            write!(self.buffer, "\x1b[?{}{}", number, action).unwrap();
+        }
+        Ok(())
+    }
+
     async fn flush(&mut self) -> io::Result<()> {
-        self.writer.write_all(&self.buffer.as_bytes())?;
+        self.writer.write_all(self.buffer.as_bytes())?;
         self.buffer.clear();
         self.writer.flush()
     }
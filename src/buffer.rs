@@ -1,59 +1,274 @@
-use std::ops::{Deref, DerefMut};
+use std::ops::Range;
 
 use crate::cell::Cell;
 use crate::layout::Rect;
 
+/// A scroll of the rows `top..bottom` of the cast by `n` rows, up if `n` is positive, down
+/// otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Scroll {
+    pub top: usize,
+    pub bottom: usize,
+    pub n: isize,
+}
+
+struct Row {
+    cells: Vec<Cell>,
+    /// columns that may have changed since last draw.
+    damage: Range<usize>,
+}
+
+impl Row {
+    fn new(width: usize) -> Self {
+        Self {
+            cells: vec![Cell::default(); width],
+            damage: 0..width,
+        }
+    }
+
+    fn damage(&mut self, columns: Range<usize>) {
+        self.damage = if self.damage.is_empty() {
+            columns
+        } else {
+            std::cmp::min(self.damage.start, columns.start)
+                ..std::cmp::max(self.damage.end, columns.end)
+        };
+    }
+
+    fn reset(&mut self) {
+        self.cells.iter_mut().for_each(|cell| {
+            cell.reset();
+        });
+        self.damage(0..self.cells.len());
+    }
+}
+
+/// The grid of cells, stored as a ring of rows so that scrolling the whole screen only moves
+/// the ring's start, and scrolling a region only swaps the rows it contains.
+///
+/// Scrolls are recorded and replayed on the previous frame before diffing, so rows keep their
+/// damage when they move and the frame can be sent as scrolls plus the exposed rows.
 pub struct Buffer {
-    current: Vec<Cell>,
-    previous: Vec<Cell>,
+    rows: Vec<Row>,
+    /// index in `rows` of the first row of the screen.
+    offset: usize,
+    /// the cells as they were at last draw, by screen row.
+    previous: Vec<Vec<Cell>>,
+    /// scrolls since last draw, in order.
+    scrolls: Vec<Scroll>,
     rect: Rect,
 }
 
 impl Buffer {
-
     pub fn new(rect: Rect) -> Self {
-        eprintln!("buflen: {}", rect.width * rect.height);
-        let current = vec![Cell::default(); (rect.width * rect.height) as usize];
-        let previous = current.clone();
+        let rows = (0..rect.height).map(|_| Row::new(rect.width)).collect();
+        let previous = vec![vec![Cell::default(); rect.width]; rect.height];
         Self {
-            current,
+            rows,
+            offset: 0,
             previous,
+            scrolls: Vec::new(),
             rect,
         }
     }
 
-    /// returns an iterator over the cells that have changed since last draw.
-    pub fn diff(&mut self) -> impl Iterator<Item = (usize, usize, Cell)> + '_ {
-        let width = self.rect.width;
-        let x = self.rect.x;
-        let y = self.rect.y;
-        std::mem::swap(&mut self.current, &mut self.previous);
-        let previous = &mut self.previous;
-        self.current
-            .iter_mut()
-            .enumerate()
-            .filter_map(move |(i, c)| {
-                if previous[i] != *c {
-                    *c = previous[i];
-                    Some((i % width + x, i / width + y, *c))
-                } else {
-                    None
+    #[inline]
+    fn index(&self, y: usize) -> usize {
+        (self.offset + y) % self.rect.height
+    }
+
+    pub fn row(&self, y: usize) -> &[Cell] {
+        &self.rows[self.index(y)].cells
+    }
+
+    /// Returns row `y` as it was at last draw.
+    pub fn drawn_row(&self, y: usize) -> &[Cell] {
+        &self.previous[y]
+    }
+
+    /// Returns the cells of row `y`, marking them as damaged.
+    pub fn row_mut(&mut self, y: usize) -> &mut [Cell] {
+        let index = self.index(y);
+        let row = &mut self.rows[index];
+        row.damage(0..row.cells.len());
+        &mut row.cells
+    }
+
+    pub fn cell_mut(&mut self, x: usize, y: usize) -> Option<&mut Cell> {
+        if y >= self.rect.height || x >= self.rect.width {
+            return None;
+        }
+        let index = self.index(y);
+        let row = &mut self.rows[index];
+        row.damage(x..x + 1);
+        row.cells.get_mut(x)
+    }
+
+    /// Records a scroll, merging it with the previous one if they are in the same direction in
+    /// the same region.
+    fn push_scroll(&mut self, range: Range<usize>, n: isize) {
+        if n == 0 {
+            return;
+        }
+        match self.scrolls.last_mut() {
+            Some(last)
+                if last.top == range.start
+                    && last.bottom == range.end
+                    && last.n.signum() == n.signum() =>
+            {
+                last.n = (last.n + n).clamp(-(range.len() as isize), range.len() as isize);
+            }
+            _ => self.scrolls.push(Scroll {
+                top: range.start,
+                bottom: range.end,
+                n,
+            }),
+        }
+    }
+
+    /// Moves the rows of `range` up by `n`, the `n` rows at the bottom of the range being blank.
+    pub fn scroll_up(&mut self, range: Range<usize>, n: usize) {
+        let n = std::cmp::min(n, range.len());
+        if range.len() == self.rect.height {
+            self.offset = (self.offset + n) % self.rect.height;
+        } else {
+            for y in range.start..range.end - n {
+                let (a, b) = (self.index(y), self.index(y + n));
+                self.rows.swap(a, b);
+            }
+        }
+        self.push_scroll(range.clone(), n as isize);
+        for y in range.end - n..range.end {
+            let index = self.index(y);
+            self.rows[index].reset();
+        }
+    }
+
+    /// Moves the rows of `range` down by `n`, the `n` rows at the top of the range being blank.
+    pub fn scroll_down(&mut self, range: Range<usize>, n: usize) {
+        let n = std::cmp::min(n, range.len());
+        if range.len() == self.rect.height {
+            self.offset = (self.offset + self.rect.height - n) % self.rect.height;
+        } else {
+            for y in (range.start + n..range.end).rev() {
+                let (a, b) = (self.index(y), self.index(y - n));
+                self.rows.swap(a, b);
+            }
+        }
+        self.push_scroll(range.clone(), -(n as isize));
+        for y in range.start..range.start + n {
+            let index = self.index(y);
+            self.rows[index].reset();
+        }
+    }
+
+    /// Applies the scrolls since last draw to the previous frame, so that the rows that only
+    /// moved are not reported as changed.
+    fn scroll_previous(&mut self) -> Vec<Scroll> {
+        let scrolls = std::mem::take(&mut self.scrolls);
+        for scroll in &scrolls {
+            let region = &mut self.previous[scroll.top..scroll.bottom];
+            let n = scroll.n.unsigned_abs();
+            let exposed = if scroll.n > 0 {
+                region.rotate_left(n);
+                region.len() - n..region.len()
+            } else {
+                region.rotate_right(n);
+                0..n
+            };
+            region[exposed]
+                .iter_mut()
+                .for_each(|row| row.iter_mut().for_each(|cell| *cell = Cell::default()));
+        }
+        scrolls
+    }
+
+    /// returns the scrolls, and the cells that have changed since last draw once the scrolls are
+    /// applied.
+    pub fn diff(&mut self) -> (Vec<Scroll>, Vec<(usize, usize, Cell)>) {
+        let scrolls = self.scroll_previous();
+        let mut changes = Vec::new();
+        for (y, previous) in self.previous.iter_mut().enumerate() {
+            let row = &mut self.rows[(self.offset + y) % self.rect.height];
+            let damage = std::mem::replace(&mut row.damage, 0..0);
+            for x in damage {
+                if row.cells[x] != previous[x] {
+                    previous[x] = row.cells[x];
+                    changes.push((x, y, row.cells[x]));
                 }
-            })
+            }
+        }
+        (scrolls, changes)
     }
 }
 
-impl Deref for Buffer {
-    type Target = Vec<Cell>;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    fn deref(&self) -> &Self::Target {
-        &self.current
+    /// A `width` x `height` buffer whose row `y` is filled with the `y`th letter, and drawn.
+    fn buffer(width: usize, height: usize) -> Buffer {
+        let mut buffer = Buffer::new(Rect::new(0, 0, width, height));
+        for y in 0..height {
+            let symbol = (b'a' + y as u8) as char;
+            buffer.row_mut(y).iter_mut().for_each(|cell| {
+                cell.set_symbol(symbol);
+            });
+        }
+        buffer.diff();
+        buffer
     }
-}
 
-impl DerefMut for Buffer {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.current
+    fn rows(buffer: &Buffer) -> Vec<String> {
+        (0..buffer.rect.height)
+            .map(|y| buffer.row(y).iter().map(|cell| cell.symbol).collect())
+            .collect()
+    }
+
+    #[test]
+    fn scroll_the_whole_screen() {
+        let mut buffer = buffer(2, 3);
+        buffer.scroll_up(0..3, 1);
+        assert_eq!(buffer.offset, 1);
+        assert_eq!(rows(&buffer), ["bb", "cc", "  "]);
+        buffer.scroll_down(0..3, 2);
+        assert_eq!(rows(&buffer), ["  ", "  ", "bb"]);
     }
-}
 
+    #[test]
+    fn scroll_a_region() {
+        let mut buffer = buffer(2, 4);
+        buffer.scroll_up(1..3, 1);
+        assert_eq!(buffer.offset, 0);
+        assert_eq!(rows(&buffer), ["aa", "cc", "  ", "dd"]);
+        buffer.scroll_down(0..4, 1);
+        buffer.scroll_up(1..4, 5);
+        assert_eq!(rows(&buffer), ["  ", "  ", "  ", "  "]);
+    }
+
+    #[test]
+    fn diff_sends_the_scroll_and_the_exposed_row() {
+        let mut buffer = buffer(2, 3);
+        buffer.scroll_up(0..3, 1);
+        buffer.scroll_up(0..3, 1);
+        buffer.cell_mut(0, 2).unwrap().set_symbol('x');
+        let (scrolls, cells) = buffer.diff();
+        assert_eq!(scrolls, [Scroll { top: 0, bottom: 3, n: 2 }]);
+        let symbols: Vec<_> = cells.iter().map(|&(x, y, cell)| (x, y, cell.symbol)).collect();
+        assert_eq!(symbols, [(0, 2, 'x')]);
+        assert_eq!(buffer.diff(), (Vec::new(), Vec::new()));
+    }
+
+    #[test]
+    fn scrolls_in_opposite_directions_are_kept() {
+        let mut buffer = buffer(2, 3);
+        buffer.scroll_up(0..3, 1);
+        buffer.scroll_down(0..3, 1);
+        let (scrolls, cells) = buffer.diff();
+        assert_eq!(scrolls.len(), 2);
+        // the first row was scrolled out and back as a blank one.
+        let symbols: Vec<_> = cells.iter().map(|&(x, y, cell)| (x, y, cell.symbol)).collect();
+        assert!(symbols.is_empty());
+        assert_eq!(rows(&buffer), ["  ", "bb", "cc"]);
+    }
+}
@@ -0,0 +1,64 @@
+use std::str::FromStr;
+
+/// What to do when the casted program writes to the clipboard with OSC 52.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ClipboardPolicy {
+    /// Copy to the host's clipboard only.
+    #[default]
+    Forward,
+    /// Drop the copy.
+    Deny,
+    /// Copy to the host's clipboard and send it to the viewers.
+    Share,
+}
+
+impl FromStr for ClipboardPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "forward" => Ok(ClipboardPolicy::Forward),
+            "deny" => Ok(ClipboardPolicy::Deny),
+            "share" => Ok(ClipboardPolicy::Share),
+            other => Err(format!("invalid clipboard policy: {}", other)),
+        }
+    }
+}
+
+/// An OSC 52 clipboard operation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Clipboard {
+    /// Selection parameter, e.g `c` for the clipboard, `p` for the primary selection.
+    pub selection: String,
+    /// Base64 encoded content, or `?` for a read request.
+    pub content: String,
+}
+
+impl Clipboard {
+    pub fn is_read(&self) -> bool {
+        self.content == "?"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_policy() {
+        assert_eq!("forward".parse(), Ok(ClipboardPolicy::Forward));
+        assert_eq!("deny".parse(), Ok(ClipboardPolicy::Deny));
+        assert_eq!("share".parse(), Ok(ClipboardPolicy::Share));
+        assert!("copy".parse::<ClipboardPolicy>().is_err());
+    }
+
+    #[test]
+    fn read_request() {
+        let clipboard = |content: &str| Clipboard {
+            selection: "c".to_string(),
+            content: content.to_string(),
+        };
+        assert!(clipboard("?").is_read());
+        assert!(!clipboard("aGVsbG8=").is_read());
+    }
+}
@@ -0,0 +1,325 @@
+use std::str::FromStr;
+
+use crate::host::Privacy;
+use crate::network::Permission;
+
+/// Key that starts a host command, like tmux's `C-b`. It is written `C-x`, for a control key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PrefixKey(u8);
+
+impl FromStr for PrefixKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.as_bytes() {
+            [b'C', b'-', key] if key.is_ascii_alphabetic() || b"@[\\]^_".contains(key) => {
+                Ok(PrefixKey(key.to_ascii_uppercase() & 0x1f))
+            }
+            _ => Err(format!("invalid prefix key: {}, expected C-<key>", s)),
+        }
+    }
+}
+
+impl PrefixKey {
+    /// Name of the key, as shown to the user.
+    pub fn name(self) -> String {
+        format!("C-{}", ((self.0 | 0x40) as char).to_ascii_lowercase())
+    }
+}
+
+/// Something the host asked termcast to do, from the command mode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HostCommand {
+    /// Stop sending frames to the viewers, or restart if the broadcast is already paused that
+    /// way.
+    Pause(Privacy),
+    Kick(usize),
+    /// Refuse an address or a viewer name for the rest of the cast.
+    Ban(String),
+    /// Give the floor to the viewer, or take it back if it has it.
+    Grant(usize),
+    /// Take the floor back from the viewer that has it.
+    TakeFloor,
+    /// Change what a viewer is allowed to do.
+    SetPermission(usize, Permission),
+    /// Send to the pty the viewer input held at a no-echo prompt.
+    SendHeld,
+    DropHeld,
+    ToggleRecording,
+    /// Clear the host screen and draw everything again.
+    Redraw,
+    /// Stop or restart sending the viewers' keys to the pty, whoever has the floor.
+    LockInput,
+    /// Give the host terminal back, the cast going on in the background.
+    Detach,
+    ListViewers,
+    /// Show how viewers authenticate.
+    ShowAuth,
+    Quit,
+}
+
+enum State {
+    /// Input goes to the pty.
+    Forward,
+    /// The prefix key was pressed, the next key is a command.
+    Command,
+    /// Reading the id of a viewer.
+    Viewer(ViewerCommand, String),
+    /// Reading what to ban.
+    Ban(Vec<u8>),
+    /// Reading the id of a viewer and its permission.
+    Permission(String),
+}
+
+/// Commands that take the id of a viewer.
+#[derive(Clone, Copy)]
+enum ViewerCommand {
+    Kick,
+    Grant,
+}
+
+/// Takes the host commands out of the input, the rest going to the pty.
+///
+/// After the prefix key, `p` pauses or resumes the broadcast, `c` does it with a placeholder
+/// screen for the viewers, `k` kicks a viewer whose id is typed next, `g` gives the floor to such
+/// a viewer, `t` takes it back, `P` sets the permission of a viewer, typed as its id and the
+/// permission, such as `3 full`, `b` bans an address or a viewer name typed next, `y` and `n`
+/// send or drop the viewer input held at a no-echo prompt, `r` toggles the recording, `l` redraws
+/// the screen, `v` lists the viewers, `a` shows the passphrase, `i` locks or unlocks the viewers'
+/// input, `d` detaches, `q` quits and the prefix key again sends it to the pty. Any other key
+/// leaves the command mode.
+pub struct CommandMode {
+    prefix: PrefixKey,
+    state: State,
+}
+
+impl CommandMode {
+    pub fn new(prefix: PrefixKey) -> Self {
+        Self {
+            prefix,
+            state: State::Forward,
+        }
+    }
+
+    pub fn prefix(&self) -> PrefixKey {
+        self.prefix
+    }
+
+    /// Returns what to show in the status line while a command is being typed.
+    pub fn prompt(&self) -> Option<String> {
+        match self.state {
+            State::Forward => None,
+            State::Command => Some(format!(
+                concat!(
+                    " [p]ause [c]urtain [k]ick [b]an [g]ive/[t]ake floor [P]ermission [r]ecord",
+                    " [l]redraw [v]iewers [a]uth [i]nput lock [d]etach [q]uit, {0} sends {0}",
+                ),
+                self.prefix.name(),
+            )),
+            State::Viewer(ViewerCommand::Kick, ref id) => Some(format!(" kick viewer: {}", id)),
+            State::Viewer(ViewerCommand::Grant, ref id) => {
+                Some(format!(" give the floor to viewer: {}", id))
+            }
+            State::Ban(ref target) => Some(format!(
+                " ban an address or a name: {}",
+                String::from_utf8_lossy(target)
+            )),
+            State::Permission(ref typed) => Some(format!(
+                " viewer and permission, read-only, request-control or full: {}",
+                typed
+            )),
+        }
+    }
+
+    /// Returns the input that goes to the pty, and the commands that were typed.
+    pub fn filter(&mut self, input: &[u8]) -> (Vec<u8>, Vec<HostCommand>) {
+        let mut forward = Vec::with_capacity(input.len());
+        let mut commands = Vec::new();
+        for &byte in input {
+            self.state = match std::mem::replace(&mut self.state, State::Forward) {
+                State::Forward if byte == self.prefix.0 => State::Command,
+                State::Forward => {
+                    forward.push(byte);
+                    State::Forward
+                }
+                State::Command if byte == b'k' => State::Viewer(ViewerCommand::Kick, String::new()),
+                State::Command if byte == b'g' => {
+                    State::Viewer(ViewerCommand::Grant, String::new())
+                }
+                State::Command if byte == b'b' => State::Ban(Vec::new()),
+                State::Command if byte == b'P' => State::Permission(String::new()),
+                State::Command => {
+                    let command = match byte {
+                        b'p' => Some(HostCommand::Pause(Privacy::Freeze)),
+                        b'c' => Some(HostCommand::Pause(Privacy::Curtain)),
+                        b'r' => Some(HostCommand::ToggleRecording),
+                        b'l' => Some(HostCommand::Redraw),
+                        b'v' => Some(HostCommand::ListViewers),
+                        b'a' => Some(HostCommand::ShowAuth),
+                        b't' => Some(HostCommand::TakeFloor),
+                        b'y' => Some(HostCommand::SendHeld),
+                        b'n' => Some(HostCommand::DropHeld),
+                        b'i' => Some(HostCommand::LockInput),
+                        b'd' => Some(HostCommand::Detach),
+                        b'q' => Some(HostCommand::Quit),
+                        b if b == self.prefix.0 => {
+                            forward.push(b);
+                            None
+                        }
+                        _ => None,
+                    };
+                    commands.extend(command);
+                    State::Forward
+                }
+                State::Viewer(command, mut id) => match byte {
+                    b'0'..=b'9' if id.len() < 8 => {
+                        id.push(byte as char);
+                        State::Viewer(command, id)
+                    }
+                    // backspace
+                    0x7f | 0x08 => {
+                        id.pop();
+                        State::Viewer(command, id)
+                    }
+                    b'\r' | b'\n' => {
+                        commands.extend(id.parse().ok().map(match command {
+                            ViewerCommand::Kick => HostCommand::Kick,
+                            ViewerCommand::Grant => HostCommand::Grant,
+                        }));
+                        State::Forward
+                    }
+                    _ => State::Forward,
+                },
+                State::Ban(mut target) => match byte {
+                    // backspace, removing a whole UTF-8 character
+                    0x7f | 0x08 => {
+                        while let Some(byte) = target.pop() {
+                            if byte & 0xc0 != 0x80 {
+                                break;
+                            }
+                        }
+                        State::Ban(target)
+                    }
+                    b'\r' | b'\n' => {
+                        let target = String::from_utf8_lossy(&target).trim().to_string();
+                        if !target.is_empty() {
+                            commands.push(HostCommand::Ban(target));
+                        }
+                        State::Forward
+                    }
+                    byte if byte >= 0x20 => {
+                        if target.len() < 64 {
+                            target.push(byte);
+                        }
+                        State::Ban(target)
+                    }
+                    _ => State::Forward,
+                },
+                State::Permission(mut typed) => match byte {
+                    0x7f | 0x08 => {
+                        typed.pop();
+                        State::Permission(typed)
+                    }
+                    b'\r' | b'\n' => {
+                        commands.extend(parse_permission(&typed));
+                        State::Forward
+                    }
+                    // the permissions are ASCII.
+                    0x20..=0x7e if typed.len() < 32 => {
+                        typed.push(byte as char);
+                        State::Permission(typed)
+                    }
+                    0x20..=0x7e => State::Permission(typed),
+                    _ => State::Forward,
+                },
+            };
+        }
+        (forward, commands)
+    }
+}
+
+/// Parses a viewer id and a permission, such as `3 full`.
+fn parse_permission(typed: &str) -> Option<HostCommand> {
+    let mut words = typed.split_whitespace();
+    let id = words.next()?.parse().ok()?;
+    let permission = words.next()?.parse().ok()?;
+    words
+        .next()
+        .is_none()
+        .then_some(HostCommand::SetPermission(id, permission))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mode() -> CommandMode {
+        CommandMode::new("C-]".parse().unwrap())
+    }
+
+    #[test]
+    fn parse_prefix_key() {
+        assert_eq!("C-b".parse(), Ok(PrefixKey(0x02)));
+        assert_eq!("C-]".parse::<PrefixKey>().map(PrefixKey::name), Ok("C-]".to_string()));
+        assert!("b".parse::<PrefixKey>().is_err());
+        assert!("C-1".parse::<PrefixKey>().is_err());
+    }
+
+    #[test]
+    fn commands_are_taken_out_of_the_input() {
+        let mut mode = mode();
+        let (forward, commands) = mode.filter(b"ab\x1dpcd\x1dq");
+        assert_eq!(forward, b"abcd");
+        assert_eq!(commands, [HostCommand::Pause(Privacy::Freeze), HostCommand::Quit]);
+        assert_eq!(mode.prompt(), None);
+    }
+
+    #[test]
+    fn prefix_key_twice_is_sent() {
+        let (forward, commands) = mode().filter(b"\x1d\x1d");
+        assert_eq!(forward, b"\x1d");
+        assert!(commands.is_empty());
+    }
+
+    #[test]
+    fn command_split_across_reads() {
+        let mut mode = mode();
+        assert_eq!(mode.filter(b"\x1d"), (Vec::new(), Vec::new()));
+        assert!(mode.prompt().is_some());
+        assert_eq!(mode.filter(b"d"), (Vec::new(), vec![HostCommand::Detach]));
+    }
+
+    #[test]
+    fn viewer_id_with_backspace() {
+        let mut mode = mode();
+        let (_, commands) = mode.filter(b"\x1dk12\x7f3\r");
+        assert_eq!(commands, [HostCommand::Kick(13)]);
+        let (forward, commands) = mode.filter(b"\x1dg\rx");
+        assert_eq!(forward, b"x");
+        assert!(commands.is_empty());
+    }
+
+    #[test]
+    fn ban_target() {
+        let mut mode = mode();
+        mode.filter("\x1db10.0.0.1 ".as_bytes());
+        assert_eq!(mode.prompt().as_deref(), Some(" ban an address or a name: 10.0.0.1 "));
+        let (_, commands) = mode.filter("\x1b".as_bytes());
+        assert!(commands.is_empty());
+        assert_eq!(mode.prompt(), None);
+
+        let (_, commands) = mode.filter("\x1dbzoé\x7fe\r".as_bytes());
+        assert_eq!(commands, [HostCommand::Ban("zoe".to_string())]);
+    }
+
+    #[test]
+    fn viewer_permission() {
+        let mut mode = mode();
+        let (_, commands) = mode.filter(b"\x1dP3 fulk\x7fl\r");
+        assert_eq!(commands, [HostCommand::SetPermission(3, Permission::Full)]);
+        for typed in ["3", "3 admin", "x read-only", "3 full 4"] {
+            let (_, commands) = mode.filter(format!("\x1dP{}\r", typed).as_bytes());
+            assert!(commands.is_empty());
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use crate::buffer::Scroll;
+use crate::cell::Cell;
+use crate::clipboard::Clipboard;
+use crate::palette::PaletteChange;
+use crate::shell::CommandEvent;
+
+/// Everything that changed in the cast since the last draw.
+#[derive(Debug, Clone, Default)]
+pub struct Frame {
+    /// Scrolls to apply, in order, before drawing the cells.
+    pub scrolls: Vec<Scroll>,
+    /// Changed cells, in cast coordinates.
+    pub cells: Vec<(usize, usize, Cell)>,
+    pub clipboard: Option<Clipboard>,
+    pub palette: Vec<PaletteChange>,
+    /// Commands that finished since the last draw.
+    pub commands: Vec<CommandEvent>,
+}
+
+impl Frame {
+    pub fn is_empty(&self) -> bool {
+        self.scrolls.is_empty()
+            && self.cells.is_empty()
+            && self.clipboard.is_none()
+            && self.palette.is_empty()
+            && self.commands.is_empty()
+    }
+
+    /// Adds the changes of `next`, drawn after this frame, so that the viewers get both at once.
+    pub fn merge(&mut self, next: Frame) {
+        // the cells of this frame are drawn before the scrolls of the next one, so they move
+        // with them, or disappear if they are scrolled out of their region.
+        for scroll in &next.scrolls {
+            self.cells.retain_mut(|(_, y, _)| {
+                if *y < scroll.top || *y >= scroll.bottom {
+                    return true;
+                }
+                let moved = *y as isize - scroll.n;
+                if moved < scroll.top as isize || moved >= scroll.bottom as isize {
+                    return false;
+                }
+                *y = moved as usize;
+                true
+            });
+        }
+        self.scrolls.extend(next.scrolls);
+        self.cells.extend(next.cells);
+        if next.clipboard.is_some() {
+            self.clipboard = next.clipboard;
+        }
+        self.palette.extend(next.palette);
+        self.commands.extend(next.commands);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cell(symbol: char) -> Cell {
+        *Cell::default().set_symbol(symbol)
+    }
+
+    #[test]
+    fn merged_cells_move_with_the_next_scrolls() {
+        let mut frame = Frame {
+            cells: vec![(0, 0, cell('a')), (0, 2, cell('b')), (0, 3, cell('c'))],
+            ..Frame::default()
+        };
+        let scroll = Scroll { top: 0, bottom: 3, n: 1 };
+        frame.merge(Frame {
+            scrolls: vec![scroll],
+            cells: vec![(0, 2, cell('d'))],
+            ..Frame::default()
+        });
+        assert_eq!(frame.scrolls, [scroll]);
+        // `a` is scrolled out of the region, `c` is outside of it.
+        assert_eq!(
+            frame.cells,
+            [(0, 1, cell('b')), (0, 3, cell('c')), (0, 2, cell('d'))]
+        );
+    }
+
+    #[test]
+    fn merged_scrolls_down() {
+        let mut frame = Frame {
+            cells: vec![(1, 1, cell('a')), (1, 2, cell('b'))],
+            ..Frame::default()
+        };
+        frame.merge(Frame {
+            scrolls: vec![Scroll { top: 1, bottom: 3, n: -1 }],
+            ..Frame::default()
+        });
+        assert_eq!(frame.cells, [(1, 2, cell('a'))]);
+    }
+}
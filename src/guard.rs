@@ -0,0 +1,168 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use anyhow::Result;
+use nix::errno::Errno;
+use nix::libc;
+use nix::sys::signal::{kill, sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use nix::sys::termios::{tcgetattr, tcsetattr, SetArg, Termios};
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::{dup2, fork, setsid, ForkResult, Pid};
+use once_cell::sync::OnceCell;
+
+/// Attributes of the host terminal before termcast changed them.
+static SAVED_TERMIOS: Mutex<Option<Termios>> = Mutex::new(None);
+
+/// Leaves the alternate screen, resets colors, palette, scroll region and modes that may have
+/// been mirrored from the casted program, and shows the cursor.
+const RESET: &str = concat!(
+    "\x1b[0m",
+    "\x1b[r",
+    "\x1b[?1000l\x1b[?1002l\x1b[?1003l\x1b[?1004l\x1b[?1006l\x1b[?2004l",
+    "\x1b]104\x07\x1b]110\x07\x1b]111\x07\x1b]112\x07",
+    "\x1b[?1049l",
+    "\x1b[?25h",
+);
+
+/// Restores the host terminal when the cast ends, whichever way it ends: normal exit, error,
+/// panic, or termination signal, as long as they are handled in `Host::run`.
+pub struct TerminalGuard {
+    start: Instant,
+    restored: bool,
+}
+
+impl TerminalGuard {
+    pub fn new() -> Result<Self> {
+        let stdout = std::io::stdout();
+        *SAVED_TERMIOS.lock().unwrap() = Some(tcgetattr(stdout.as_raw_fd())?);
+
+        let default_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            restore();
+            default_hook(info);
+            // the cast can't go on with the host terminal in cooked mode.
+            std::process::exit(101);
+        }));
+
+        Ok(Self {
+            start: Instant::now(),
+            restored: false,
+        })
+    }
+
+    /// Restores the terminal and prints a summary of the cast, `reason` being why it ended.
+    pub fn finish(mut self, reason: &str) {
+        restore();
+        self.restored = true;
+        let elapsed = self.start.elapsed().as_secs();
+        eprintln!(
+            "termcast: cast ended after {}h{:02}m{:02}s: {}",
+            elapsed / 3600,
+            elapsed / 60 % 60,
+            elapsed % 60,
+            reason
+        );
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        if !self.restored {
+            restore();
+        }
+    }
+}
+
+fn restore() {
+    let mut stdout = std::io::stdout();
+    let _ = stdout.write_all(RESET.as_bytes());
+    let _ = stdout.flush();
+    if let Ok(saved) = SAVED_TERMIOS.lock() {
+        if let Some(ref termios) = *saved {
+            let _ = tcsetattr(stdout.as_raw_fd(), SetArg::TCSANOW, termios);
+        }
+    }
+}
+
+/// Process running the cast, for the parent to pass it the signals it gets.
+static CHILD: AtomicI32 = AtomicI32::new(0);
+/// What the parent prints when the host detaches.
+static DETACHED: OnceCell<String> = OnceCell::new();
+
+/// Lets the host leave the cast running in the background, and get its terminal back.
+///
+/// The cast runs in a child process, the process started from the shell only waiting for it. On
+/// detach, the child lets go of the terminal, and the parent exits, as if the cast had ended.
+pub struct Detach {
+    parent: Pid,
+}
+
+impl Detach {
+    /// Forks, returning in the child only. The parent exits with the child's status, or when it
+    /// detaches.
+    ///
+    /// Must be called before any thread is started, as they are not copied by the fork.
+    pub fn fork() -> Result<Self> {
+        let parent = nix::unistd::getpid();
+        match unsafe { fork() }? {
+            ForkResult::Child => Ok(Self { parent }),
+            ForkResult::Parent { child } => wait(child),
+        }
+    }
+
+    /// Restores the host terminal, and stops reading and writing it. The cast goes on until the
+    /// shell exits, or termcast is told to stop with a signal.
+    pub fn detach(self) -> Result<()> {
+        restore();
+        let null = OpenOptions::new().read(true).write(true).open("/dev/null")?;
+        for fd in 0..3 {
+            dup2(null.as_raw_fd(), fd)?;
+        }
+        // so that closing the terminal doesn't hang up the cast.
+        setsid()?;
+        kill(self.parent, Signal::SIGUSR1)?;
+        Ok(())
+    }
+}
+
+/// Waits for the cast, passing it the signals that would have ended it.
+fn wait(child: Pid) -> ! {
+    CHILD.store(child.as_raw(), Ordering::SeqCst);
+    let _ = DETACHED.set(format!(
+        "termcast: detached, the cast goes on in process {}, `kill {}` ends it\n",
+        child, child
+    ));
+    let handle = |signal, handler| {
+        let handler = SigHandler::Handler(handler);
+        let action = SigAction::new(handler, SaFlags::empty(), SigSet::empty());
+        let _ = unsafe { sigaction(signal, &action) };
+    };
+    for signal in [Signal::SIGTERM, Signal::SIGHUP, Signal::SIGINT] {
+        handle(signal, forward);
+    }
+    handle(Signal::SIGUSR1, detached);
+    let code = loop {
+        match waitpid(child, None) {
+            Ok(WaitStatus::Exited(_, code)) => break code,
+            Ok(WaitStatus::Signaled(_, signal, _)) => break 128 + signal as i32,
+            Err(nix::Error::Sys(Errno::EINTR)) | Ok(_) => continue,
+            Err(_) => break 1,
+        }
+    };
+    std::process::exit(code)
+}
+
+extern "C" fn forward(signal: libc::c_int) {
+    unsafe { libc::kill(CHILD.load(Ordering::SeqCst), signal) };
+}
+
+extern "C" fn detached(_: libc::c_int) {
+    if let Some(message) = DETACHED.get() {
+        unsafe { libc::write(2, message.as_ptr() as *const _, message.len()) };
+    }
+    unsafe { libc::_exit(0) };
+}
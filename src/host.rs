@@ -1,37 +1,142 @@
 use std::convert::TryFrom;
-use std::io::{stdin, Stdout};
-use std::os::unix::io::AsRawFd;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, stdin, Stdout, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::{Path, PathBuf};
 use std::process::Command;
-use std::time::{Duration, Instant};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::net::SocketAddr;
+use std::ops::Range;
+use std::sync::Arc;
 
-use anyhow::Result;
+use anyhow::{bail, ensure, Context, Result};
+use log::error;
 use nix::ioctl_read_bad;
 use nix::libc::TIOCGWINSZ;
+use nix::poll::{poll, PollFd, PollFlags};
 use nix::pty::{forkpty, Winsize};
+use nix::sys::termios::{tcgetattr, LocalFlags};
 use nix::unistd::ForkResult;
 use termion::raw::IntoRawMode;
 use tokio::io::{split, AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::{mpsc, broadcast};
+use tokio::time::{sleep_until, Instant};
 use tokio_fd::AsyncFd;
 
 use crate::backends::{Backend, TermionBackend};
-use crate::layout::Rect;
+use crate::cell::Cell;
+use crate::command::{CommandMode, HostCommand};
+use crate::frame::Frame;
+use crate::guard::Detach;
+use crate::input::MouseTranslator;
+use crate::layout::{Fit, Rect};
+use crate::mode::Mode;
+use crate::palette::{self, Palette, PaletteIndex, Rgb};
 use crate::terminal::Terminal;
-use crate::network::Network;
+use crate::network::protocol::Message;
+use crate::network::access::{Access, Ban};
+use crate::network::auth::Auth;
+use crate::network::registry::{Registry, Viewer};
+use crate::network::tls::Tls;
+use crate::network::{Network, NetworkCommand, NetworkEvent, Permission};
+use crate::record::Recorder;
+use crate::redact::{self, Redactor};
+use crate::status::Status;
+use crate::CastOptions;
 
-const FPS: u64 = 60;
+/// Number of stdin chunks that can be waiting to be written to the pty before the reader blocks.
+const STDIN_QUEUE: usize = 16;
+/// Stdin is not read while this many bytes are waiting to be written to the pty.
+const MAX_PENDING_INPUT: usize = 64 * 1024;
+/// Most rows sent to a viewer that asks for the scrollback.
+const MAX_LINES_PER_REQUEST: usize = 1000;
+/// How long the host terminal has to answer the palette queries.
+const PALETTE_QUERY_TIMEOUT: Duration = Duration::from_millis(500);
 
 ioctl_read_bad!(get_win_size, TIOCGWINSZ, Winsize);
 
+/// Why a cast ended.
+pub enum ExitReason {
+    ShellExited,
+    /// The host used the quit command.
+    Quit,
+    Signal(&'static str),
+}
+
+impl fmt::Display for ExitReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExitReason::ShellExited => write!(f, "the shell exited"),
+            ExitReason::Quit => write!(f, "quit by the host"),
+            ExitReason::Signal(name) => write!(f, "received {}", name),
+        }
+    }
+}
+
 pub struct Host {
     terminal: Terminal<TermionBackend<termion::raw::RawTerminal<Stdout>>>,
     parser: vte::Parser,
     master: AsyncFd,
+    /// the master, to read the pty's termios.
+    master_fd: RawFd,
+    mouse: MouseTranslator,
+    fps: u32,
+    viewer_fps: u32,
+    /// size of the host terminal.
+    host_size: (usize, usize),
+    commands: CommandMode,
+    record_path: Option<PathBuf>,
+    redactor: Redactor,
+    /// where the viewers connect.
+    listen: SocketAddr,
+    /// who can connect.
+    access: Access,
+    tls: Option<Tls>,
+    /// what viewers can do when they connect.
+    viewer_permission: Permission,
+    /// whether `full` viewers take a free floor without asking.
+    auto_grant: bool,
+    /// lets the host leave the cast running, if it runs in a process of its own.
+    detach: Option<Detach>,
 }
 
 impl Host {
-    pub async fn new(cols: usize, rows: usize) -> Result<Self> {
+    pub async fn new(options: &CastOptions) -> Result<Self> {
+        let CastOptions { cols, rows, fps, fit, .. } = *options;
+        let viewer_fps = options.viewer_fps.unwrap_or(fps);
+        ensure!(fps > 0 && viewer_fps > 0, "the frame rate must be at least 1");
+
+        let mut host_winsize = Winsize {
+            ws_row: 0,
+            ws_col: 0,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        unsafe { get_win_size(std::io::stdout().as_raw_fd(), &mut host_winsize as *mut _) }?;
+        let (host_cols, host_rows) = (host_winsize.ws_col as usize, host_winsize.ws_row as usize);
+        let fits = cols <= host_cols && rows <= host_rows;
+        let (cols, rows) = match fit {
+            Fit::Error if !fits => bail!(
+                "the cast is {}x{} but the terminal is only {}x{}, resize the terminal, or use \
+                 smaller -c/-r or --fit shrink|viewport",
+                cols,
+                rows,
+                host_cols,
+                host_rows
+            ),
+            Fit::Shrink => (cols.min(host_cols).max(1), rows.min(host_rows).max(1)),
+            _ => (cols, rows),
+        };
+
+        let tls = match (&options.cert, &options.key) {
+            (Some(cert), Some(key)) => Some(Tls::from_files(cert, key)?),
+            _ if options.tls => Some(Tls::self_signed()?),
+            _ => None,
+        };
+
         let winsize = Winsize {
             ws_row: rows as u16,
             ws_col: cols as u16,
@@ -39,36 +144,64 @@ impl Host {
             ws_ypixel: 0,
         };
         let pty_fork_result = forkpty(Some(&winsize), None)?;
-        let master_fd = pty_fork_result.master;
-        let master = AsyncFd::try_from(master_fd)?;
-
         match pty_fork_result.fork_result {
             ForkResult::Parent { .. } => {
-                let stdout = std::io::stdout().into_raw_mode()?;
-                let mut master_winsize = Winsize {
-                    ws_row: 0,
-                    ws_col: 0,
-                    ws_xpixel: 0,
-                    ws_ypixel: 0,
-                };
-                unsafe { get_win_size(stdout.as_raw_fd(), &mut master_winsize as *mut _) }?;
+                // the master is only open in the parent.
+                let master = AsyncFd::try_from(pty_fork_result.master)?;
+                let mut stdout = std::io::stdout().into_raw_mode()?;
+                let host_colors = query_palette(&mut stdout)?;
+                write!(stdout, "{}", termion::screen::ToAlternateScreen)?;
 
                 let mut backend = TermionBackend::new(stdout);
                 backend.clear().await?;
 
                 let rect = Rect::new(
-                    master_winsize.ws_col as usize / 2 - cols / 2,
-                    master_winsize.ws_row as usize / 2 - rows / 2,
+                    host_cols.saturating_sub(cols) / 2,
+                    host_rows.saturating_sub(rows) / 2,
                     cols,
                     rows,
                 );
-                let terminal = Terminal::new(rect, backend);
+                let mut patterns = if options.no_default_redaction {
+                    Vec::new()
+                } else {
+                    redact::default_patterns()
+                };
+                patterns.extend(options.redact.iter().cloned());
+                let redactor = Redactor::new(patterns, &rect);
+                let access = Access::new(
+                    Auth::new(options.tokens.clone())?,
+                    options.allow.clone(),
+                    options.deny.clone(),
+                    options.max_viewers,
+                );
+
+                let mut terminal = Terminal::new(rect, backend);
+                terminal.set_host_width(host_cols);
+                if !fits && fit == Fit::Viewport {
+                    terminal.set_viewport(cols.min(host_cols), rows.min(host_rows));
+                }
+                terminal.set_clipboard_policy(options.clipboard, options.allow_clipboard_read);
+                terminal.set_initial_palette(Palette::new(&host_colors));
 
                 let parser = vte::Parser::new();
                 Ok(Self {
                     terminal,
                     parser,
                     master,
+                    master_fd: pty_fork_result.master,
+                    mouse: MouseTranslator::default(),
+                    fps,
+                    viewer_fps,
+                    host_size: (host_cols, host_rows),
+                    commands: CommandMode::new(options.prefix_key),
+                    record_path: options.record.clone(),
+                    redactor,
+                    listen: options.listen,
+                    access,
+                    tls,
+                    viewer_permission: options.viewer_permission,
+                    auto_grant: options.auto_grant,
+                    detach: None,
                 })
             }
             ForkResult::Child => {
@@ -79,74 +212,759 @@ impl Host {
         }
     }
 
-    pub async fn run(mut self) -> Result<()> {
-        let mut buf = [0; 4096];
-        let mut stdin = spawn_stdin();
+    pub fn set_detach(&mut self, detach: Detach) {
+        self.detach = Some(detach);
+    }
+
+    pub async fn run(mut self) -> Result<ExitReason> {
+        let mut buf = vec![0; 64 * 1024];
+        // closed when the host detaches, so that stdin is left to the shell.
+        let (mut stdin, mut stop_stdin) = spawn_stdin()?;
+        let mut detached = false;
         let (mut master_read, mut master_write) = split(self.master);
-        let mut last_draw_time = Instant::now();
-        let period = Duration::from_millis(1000 / FPS);
+        let mut screen = Pacer::new(self.fps);
+        let mut viewers = Pacer::new(self.viewer_fps);
+        // changes drawn on the screen but not yet sent to the viewers.
+        let mut pending = Frame::default();
+        // bytes waiting to be written to the pty. They are written as the pty takes them, so
+        // that the output keeps being read while a large paste goes through.
+        let mut input = Vec::new();
 
-        let (sender, _) = broadcast::channel(100);
-        let addr = SocketAddr::try_from(([0, 0, 0, 0], 9999))?;
-        let network = Network::new(sender.clone(), addr);
+        // frames are encoded once, and the same buffer is written to every client.
+        let (sender, _) = broadcast::channel::<Arc<[u8]>>(100);
+        let (events_sender, mut events) = mpsc::unbounded_channel();
+        let (network_commands, commands_receiver) = mpsc::unbounded_channel();
+        // shown to the host, to give to the viewers.
+        let mut auth = vec![self.access.auth.to_string()];
+        if let Some(ref tls) = self.tls {
+            auth.push(format!("fingerprint: {}", tls.fingerprint));
+        }
+        let listen = self.listen;
+        let listener = TcpListener::bind(listen)
+            .await
+            .with_context(|| format!("can't listen on {}", listen))?;
+        // with the port picked, if it was 0.
+        let addr = listener.local_addr()?;
+        let network = Network::new(
+            sender.clone(),
+            listener,
+            self.terminal.rect().clone(),
+            events_sender,
+            commands_receiver,
+            self.access,
+            self.tls.map(|tls| tls.acceptor),
+        );
 
         tokio::task::spawn(network.run());
 
-        let mut interval = tokio::time::interval(period);
+        let mut status = Status::new(addr);
+        status.notify_all(auth.clone());
+        let mut status_interval = tokio::time::interval(Duration::from_secs(1));
+        // frames drawn on the screen since the last status update.
+        let mut frames = 0;
+        let mut registry = Registry::default();
+        let mut host_commands = Vec::new();
+        // the viewer whose keys go to the pty, the host's keys going there if there is none.
+        let mut floor = None;
+        // who gets the floor next, if it changes.
+        let mut next_floor = None;
+        // input from the viewers, held while the program reads a password.
+        let mut held = Vec::new();
+        let mut privacy = None;
+        // absolute lines that were on the screen during a pause, that viewers can't read back.
+        let mut hidden: Vec<Range<usize>> = Vec::new();
+        let mut broadcast = Broadcast {
+            sender: sender.clone(),
+            recorder: None,
+            redactor: self.redactor,
+        };
+        // result of the last frames sent, that can only fail if the recording fails.
+        let mut outcome = Ok(());
+        let mut redraw_status = false;
+        // whether the pty's echo flag may have changed.
+        let mut check_echo = false;
+        // whether a viewer missed frames, and needs a keyframe.
+        let mut resync = false;
 
-        loop {
+        let mut sigterm = signal(SignalKind::terminate())?;
+        let mut sighup = signal(SignalKind::hangup())?;
+
+        let reason = 'cast: loop {
             tokio::select! {
                 result = master_read.read(&mut buf) => {
                     match result {
                         Ok(n) if n > 0 => {
-                            for byte in &buf[..n] {
-                                self.parser.advance(&mut self.terminal, *byte);
-                            }
-                            if last_draw_time.elapsed() >= period {
-                                let change = self.terminal.draw().await?;
-                                if !change.is_empty() {
-                                    let _ = sender.send(change);
-                                }
-                                last_draw_time = Instant::now();
+                            advance(&mut self.parser, &mut self.terminal, &buf[..n]);
+                            self.terminal.apply_modes().await?;
+                            input.extend(self.terminal.take_replies());
+                            check_echo = true;
+                            if screen.ready()
+                                && draw(&mut self.terminal, &mut screen, &mut pending).await?
+                            {
+                                frames += 1;
                             }
                         }
-                        _ => break,
+                        _ => break ExitReason::ShellExited,
                     }
                 },
-                result = stdin.recv() => {
+                result = stdin.recv(), if !detached && input.len() < MAX_PENDING_INPUT => {
                     match result {
-                        Some(byte) => {
-                            master_write.write(&[byte as u8]).await?;
-                            master_write.flush().await?;
+                        Some(chunk) => {
+                            let (chunk, commands) = self.commands.filter(&chunk);
+                            host_commands.extend(commands);
+                            let prompt = self.commands.prompt();
+                            if prompt != status.prompt {
+                                status.prompt = prompt;
+                                redraw_status = true;
+                            }
+                            if let Some(id) = floor.filter(|_| !chunk.is_empty()) {
+                                status.notify(format!(
+                                    "{} has the floor, {} t takes it back",
+                                    registry.label(id),
+                                    self.commands.prefix().name()
+                                ));
+                                redraw_status = true;
+                            } else if self.terminal.mode().intersects(Mode::MOUSE) {
+                                let (screen, origin) = self.terminal.visible();
+                                input.extend(self.mouse.translate(&chunk, &screen, origin));
+                            } else {
+                                input.extend(chunk);
+                            }
+                        }
+                        _ => break ExitReason::ShellExited,
+                    }
+                }
+                result = master_write.write(&input), if !input.is_empty() => {
+                    let n = result?;
+                    input.drain(..n);
+                }
+                _ = sleep_until(screen.deadline()), if screen.is_scheduled() => {
+                    screen.fire();
+                    if draw(&mut self.terminal, &mut screen, &mut pending).await? {
+                        frames += 1;
+                    }
+                }
+                _ = sleep_until(viewers.deadline()), if viewers.is_scheduled() => viewers.fire(),
+                Some(event) = events.recv() => {
+                    let prefix = self.commands.prefix().name();
+                    match event {
+                        NetworkEvent::Connected { id, addr, name, stats } => {
+                            let permission = self.viewer_permission;
+                            let connected = std::time::Instant::now();
+                            let viewer = Viewer { name, addr, connected, permission, stats };
+                            registry.insert(id, viewer);
+                            status.notify(format!("{} joined from {}", registry.label(id), addr));
+                            for message in [
+                                Message::ViewerId(id),
+                                Message::Permission(permission),
+                                Message::Floor(floor),
+                            ] {
+                                let _ = network_commands.send(NetworkCommand::Send { id, message });
+                            }
+                            broadcast.send_presence(registry.presence());
+                            // the viewer only gets the changes from now on. The keyframe goes to
+                            // every viewer, as one sent to this viewer only could be drawn before
+                            // changes already in the broadcast.
+                            match privacy {
+                                None => {
+                                    let frame = keyframe(&self.terminal, &mut pending);
+                                    outcome = broadcast.send(frame);
+                                }
+                                // what the others were left with during the pause is not kept.
+                                Some(_) => {
+                                    let message = Message::Frame(curtain(self.terminal.rect()));
+                                    let command = NetworkCommand::Send { id, message };
+                                    let _ = network_commands.send(command);
+                                }
+                            }
+                            // so that the viewer can go back to the commands run before it came.
+                            let commands = self
+                                .terminal
+                                .history()
+                                .iter()
+                                .filter(|c| !is_hidden(&hidden, c.prompt_line..c.end_line + 1))
+                                .cloned()
+                                .collect();
+                            let frame = broadcast.redactor.redact(Frame {
+                                commands,
+                                ..Frame::default()
+                            });
+                            if !frame.is_empty() {
+                                let message = Message::Frame(frame);
+                                let _ = network_commands.send(NetworkCommand::Send { id, message });
+                            }
+                        }
+                        NetworkEvent::Disconnected { id } => {
+                            status.notify(format!("{} left", registry.label(id)));
+                            registry.remove(id);
+                            broadcast.send_presence(registry.presence());
+                            if floor == Some(id) {
+                                next_floor = Some(None);
+                            }
+                        }
+                        NetworkEvent::Rejected { addr, reason } => {
+                            status.notify(format!("refused {}: {}", addr, reason));
+                        }
+                        NetworkEvent::Input { id, input: keys } => {
+                            if floor == Some(id) && !status.input_locked {
+                                // read now, as the program may have turned the echo off without
+                                // printing anything. The status is updated below.
+                                let secret_input = is_secret_input(self.master_fd);
+                                check_echo |= secret_input != status.secret_input;
+                                // the viewer can't see what it types, and may not know it is
+                                // typing a password.
+                                if secret_input {
+                                    if held.is_empty() {
+                                        status.notify(format!(
+                                            "{} typed at a no-echo prompt, {} y sends it, {} n \
+                                             drops it",
+                                            registry.label(id), prefix, prefix
+                                        ));
+                                    }
+                                    if held.len() < MAX_PENDING_INPUT {
+                                        held.extend(keys);
+                                    }
+                                } else if input.len() < MAX_PENDING_INPUT {
+                                    input.extend(keys);
+                                }
+                            }
+                        }
+                        NetworkEvent::ControlRequested { id } => match registry.get(id) {
+                            Some(_) if floor == Some(id) => {}
+                            Some(viewer) if is_auto_granted(self.auto_grant, viewer, floor) => {
+                                next_floor = Some(Some(id));
+                            }
+                            Some(viewer) if viewer.permission >= Permission::RequestControl => {
+                                status.notify(format!(
+                                    "{} asks for the floor, {} g {} Enter gives it",
+                                    registry.label(id), prefix, id
+                                ));
+                            }
+                            _ => {}
+                        },
+                        NetworkEvent::LinesRequested { id, start, count } => {
+                            let count = count.min(MAX_LINES_PER_REQUEST);
+                            let (start, mut rows) = self.terminal.lines(start, count);
+                            for (line, row) in (start..).zip(rows.iter_mut()) {
+                                if is_hidden(&hidden, line..line + 1) {
+                                    row.iter_mut().for_each(|cell| *cell = Cell::default());
+                                }
+                            }
+                            broadcast.redactor.redact_rows(&mut rows);
+                            let message = Message::Lines { start, rows };
+                            let _ = network_commands.send(NetworkCommand::Send { id, message });
                         }
-                        _ => break,
+                        NetworkEvent::Lagged => resync = true,
                     }
+                    status.viewers = registry.names();
+                    redraw_status = true;
                 }
-                _ = interval.tick() => {
-                    if last_draw_time.elapsed() >= period {
-                        let change = self.terminal.draw().await?;
-                        if !change.is_empty() {
-                            let _ = sender.send(change);
+                _ = status_interval.tick() => {
+                    status.fps = std::mem::take(&mut frames);
+                    status.title = self.terminal.title().map(String::from);
+                    redraw_status = true;
+                    check_echo = true;
+                    // at most once a second, as a slow viewer may keep lagging. A pause ends with
+                    // a keyframe anyway.
+                    if std::mem::take(&mut resync) {
+                        if privacy.is_none() {
+                            outcome = broadcast.send(keyframe(&self.terminal, &mut pending));
+                        }
+                        // the messages it missed may not all be frames.
+                        for message in [
+                            Message::Floor(floor),
+                            Message::SecretInput(status.secret_input),
+                        ] {
+                            outcome = outcome.and(broadcast.send_message(message));
+                        }
+                        broadcast.send_presence(registry.presence());
+                    }
+                }
+                _ = sigterm.recv() => break ExitReason::Signal("SIGTERM"),
+                _ = sighup.recv() => break ExitReason::Signal("SIGHUP"),
+            }
+
+            for command in host_commands.drain(..) {
+                match command {
+                    HostCommand::Pause(mode) if privacy == Some(mode) => {
+                        privacy = None;
+                        if let Some(lines) = hidden.last_mut() {
+                            lines.end = self.terminal.screen_lines().end;
+                        }
+                        // the viewers missed the frames drawn during the pause, but not the
+                        // commands that finished since, unless they were seen during it.
+                        pending.commands.retain(|c| {
+                            !is_hidden(&hidden, c.prompt_line..c.end_line + 1)
+                        });
+                        pending.clipboard = None;
+                        outcome = broadcast.send(keyframe(&self.terminal, &mut pending));
+                        status.notify("broadcast resumed".to_string());
+                    }
+                    HostCommand::Pause(mode) => {
+                        if privacy.is_none() {
+                            hidden.push(self.terminal.screen_lines().start..usize::MAX);
+                        }
+                        privacy = Some(mode);
+                        if mode == Privacy::Curtain {
+                            outcome = broadcast.send(curtain(self.terminal.rect()));
+                        }
+                        status.notify(format!("broadcast paused ({})", mode));
+                    }
+                    HostCommand::Kick(id) => {
+                        if registry.contains(id) {
+                            let _ = network_commands.send(NetworkCommand::Kick(id));
+                            status.notify(format!("kicked {}", registry.label(id)));
+                        } else {
+                            status.notify(format!("no viewer {}", id));
+                        }
+                    }
+                    HostCommand::Ban(target) => {
+                        let ban = match target.parse() {
+                            Ok(addr) => Ban::Addr(addr),
+                            Err(_) => Ban::Name(target.clone()),
+                        };
+                        let _ = network_commands.send(NetworkCommand::Ban(ban));
+                        status.notify(format!("banned {}", target));
+                    }
+                    HostCommand::Grant(id) if !registry.contains(id) => {
+                        status.notify(format!("no viewer {}", id));
+                    }
+                    HostCommand::Grant(id) if floor == Some(id) => next_floor = Some(None),
+                    HostCommand::Grant(id) => next_floor = Some(Some(id)),
+                    HostCommand::TakeFloor => next_floor = Some(None),
+                    HostCommand::SetPermission(id, permission) => {
+                        if registry.set_permission(id, permission) {
+                            let message = Message::Permission(permission);
+                            let _ = network_commands.send(NetworkCommand::Send { id, message });
+                            broadcast.send_presence(registry.presence());
+                            status.notify(format!("{} is {}", registry.label(id), permission));
+                        } else {
+                            status.notify(format!("no viewer {}", id));
+                        }
+                    }
+                    HostCommand::SendHeld if held.is_empty() => {
+                        status.notify("no viewer input held".to_string());
+                    }
+                    HostCommand::SendHeld => {
+                        input.append(&mut held);
+                        status.notify("viewer input sent".to_string());
+                    }
+                    HostCommand::DropHeld => {
+                        held.clear();
+                        status.notify("viewer input dropped".to_string());
+                    }
+                    HostCommand::ToggleRecording => match broadcast.recorder.take() {
+                        Some(recording) => {
+                            let path = recording.finish()?;
+                            status.notify(format!("recorded to {}", path.display()));
+                        }
+                        None => {
+                            let path = self.record_path.clone().unwrap_or_else(default_record_path);
+                            // a recording started during a pause doesn't show the screen either.
+                            let first = match privacy {
+                                None => {
+                                    // the keyframe already has the pending changes.
+                                    if !pending.is_empty() {
+                                        outcome = broadcast.send(std::mem::take(&mut pending));
+                                    }
+                                    broadcast.redactor.redact(self.terminal.keyframe())
+                                }
+                                Some(_) => curtain(self.terminal.rect()),
+                            };
+                            let rect = self.terminal.rect();
+                            match start_recording(&path, rect, first, status.secret_input) {
+                                Ok(recording) => {
+                                    broadcast.recorder = Some(recording);
+                                    status.notify(format!("recording to {}", path.display()));
+                                }
+                                Err(e) => {
+                                    status.notify(format!(
+                                        "can't record to {}: {}",
+                                        path.display(),
+                                        e
+                                    ));
+                                }
+                            }
                         }
-                        last_draw_time = Instant::now();
+                    },
+                    HostCommand::Redraw => {
+                        self.terminal.backend.clear().await?;
+                        self.terminal.invalidate();
+                        screen.fire();
+                        if draw(&mut self.terminal, &mut screen, &mut pending).await? {
+                            frames += 1;
+                        }
+                        if privacy.is_none() {
+                            outcome = broadcast.send(keyframe(&self.terminal, &mut pending));
+                        }
+                    }
+                    HostCommand::ListViewers if registry.is_empty() => {
+                        status.notify("no viewers".to_string());
+                    }
+                    HostCommand::ListViewers => status.notify_all(registry.describe()),
+                    HostCommand::ShowAuth => status.notify_all(auth.clone()),
+                    HostCommand::LockInput => {
+                        status.input_locked = !status.input_locked;
+                        status.notify(match status.input_locked {
+                            true => "viewer input locked".to_string(),
+                            false => "viewer input unlocked".to_string(),
+                        });
                     }
+                    HostCommand::Detach => match self.detach.take() {
+                        Some(detach) => {
+                            drop(stop_stdin.take());
+                            detach.detach()?;
+                            detached = true;
+                        }
+                        None => status.notify("can't detach".to_string()),
+                    },
+                    HostCommand::Quit => break 'cast ExitReason::Quit,
+                }
+                status.paused = privacy;
+                redraw_status = true;
+            }
+
+            if !pending.is_empty() && viewers.ready() {
+                let frame = std::mem::take(&mut pending);
+                // nothing drawn during a pause is sent, even after it.
+                if privacy.is_none() {
+                    outcome = broadcast.send(frame);
+                }
+                viewers.done();
+            }
+
+            if let Some(holder) = next_floor.take().filter(|&holder| holder != floor) {
+                floor = holder;
+                // keys held for the previous holder are not sent for the next one.
+                held.clear();
+                status.floor = floor.map(|id| registry.label(id));
+                status.notify(match floor {
+                    Some(id) => format!("{} has the floor", registry.label(id)),
+                    None => "you have the floor".to_string(),
+                });
+                outcome = outcome.and(broadcast.send_message(Message::Floor(floor)));
+                redraw_status = true;
+            }
+
+            if std::mem::take(&mut check_echo) {
+                let secret_input = is_secret_input(self.master_fd);
+                if secret_input != status.secret_input {
+                    status.secret_input = secret_input;
+                    let message = Message::SecretInput(secret_input);
+                    outcome = outcome.and(broadcast.send_message(message));
+                    redraw_status = true;
                 }
             }
+
+            if let Err(e) = std::mem::replace(&mut outcome, Ok(())) {
+                error!("recording failed: {}", e);
+                status.notify(format!("recording failed: {}", e));
+                redraw_status = true;
+            }
+            if std::mem::take(&mut redraw_status) && !detached {
+                status.recording = broadcast.recorder.is_some();
+                draw_status(&mut self.terminal, &status, self.host_size).await?;
+            }
+        };
+        if let Some(recording) = broadcast.recorder {
+            recording.finish()?;
+        }
+        Ok(reason)
+    }
+}
+
+/// How the broadcast is paused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Privacy {
+    /// The viewers keep seeing the last frame.
+    Freeze,
+    /// The viewers see a placeholder screen.
+    Curtain,
+}
+
+impl fmt::Display for Privacy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Privacy::Freeze => write!(f, "frozen"),
+            Privacy::Curtain => write!(f, "curtain"),
+        }
+    }
+}
+
+/// Sends the frames to the viewers and to the recording, if any.
+struct Broadcast {
+    sender: broadcast::Sender<Arc<[u8]>>,
+    recorder: Option<Recorder>,
+    redactor: Redactor,
+}
+
+impl Broadcast {
+    /// Redacts the frame and sends it.
+    fn send(&mut self, frame: Frame) -> io::Result<()> {
+        let frame = self.redactor.redact(frame);
+        self.send_message(Message::Frame(frame))
+    }
+
+    /// Encodes the message once for everyone. The recording is stopped if it fails.
+    fn send_message(&mut self, message: Message) -> io::Result<()> {
+        let message: Arc<[u8]> = message.encode().into();
+        let _ = self.sender.send(message.clone());
+        let result = match self.recorder {
+            Some(ref mut recorder) => recorder.record(&message),
+            None => Ok(()),
+        };
+        if result.is_err() {
+            self.recorder = None;
+        }
+        result
+    }
+
+    /// Tells the viewers who is watching. Not recorded, the viewers being nobody's business once
+    /// the cast is over.
+    fn send_presence(&self, presence: Message) {
+        let _ = self.sender.send(presence.encode().into());
+    }
+}
+
+/// Returns the frame shown to the viewers while the broadcast is behind the curtain.
+fn curtain(rect: &Rect) -> Frame {
+    const TEXT: &str = "the host paused the broadcast";
+    let mut cells = Vec::with_capacity(rect.width * rect.height);
+    let (text_x, text_y) = (rect.width.saturating_sub(TEXT.len()) / 2, rect.height / 2);
+    for y in 0..rect.height {
+        for x in 0..rect.width {
+            let symbol = match x.checked_sub(text_x) {
+                Some(i) if y == text_y => TEXT.chars().nth(i).unwrap_or(' '),
+                _ => ' ',
+            };
+            cells.push((x, y, Cell { symbol, ..Cell::default() }));
+        }
+    }
+    Frame {
+        cells,
+        ..Frame::default()
+    }
+}
+
+/// Starts a recording with `first` as the first frame, so that it can be played on its own.
+fn start_recording(
+    path: &Path,
+    rect: &Rect,
+    first: Frame,
+    secret_input: bool,
+) -> io::Result<Recorder> {
+    let mut recorder = Recorder::create(path)?;
+    recorder.record(&Message::Hello {
+        width: rect.width,
+        height: rect.height,
+    }
+    .encode())?;
+    recorder.record(&Message::Frame(first).encode())?;
+    if secret_input {
+        recorder.record(&Message::SecretInput(true).encode())?;
+    }
+    Ok(recorder)
+}
+
+/// Returns whether the program on the pty reads a line without echoing it, as `sudo` or
+/// `read -s` do for passwords. Programs that read the keys one by one, like editors, turn the echo
+/// off too, but they also leave the canonical mode.
+fn is_secret_input(master: RawFd) -> bool {
+    match tcgetattr(master) {
+        Ok(termios) => {
+            let flags = termios.local_flags;
+            flags.contains(LocalFlags::ICANON) && !flags.contains(LocalFlags::ECHO)
+        }
+        Err(_) => false,
+    }
+}
+
+/// Returns whether a viewer that asks for the floor gets it without the host's approval.
+fn is_auto_granted(auto_grant: bool, viewer: &Viewer, floor: Option<usize>) -> bool {
+    auto_grant && viewer.permission == Permission::Full && floor.is_none()
+}
+
+fn default_record_path() -> PathBuf {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    PathBuf::from(format!("termcast-{}.rec", now))
+}
+
+/// Returns whether some of the absolute `lines` were on the screen during a pause.
+fn is_hidden(hidden: &[Range<usize>], lines: Range<usize>) -> bool {
+    hidden.iter().any(|h| h.start < lines.end && lines.start < h.end)
+}
+
+/// Returns the keyframe, in place of the pending frame. The commands that finished and the
+/// clipboard are kept from it, as the keyframe only has the screen.
+fn keyframe<B: Backend>(terminal: &Terminal<B>, pending: &mut Frame) -> Frame {
+    let pending = std::mem::take(pending);
+    Frame {
+        clipboard: pending.clipboard,
+        commands: pending.commands,
+        ..terminal.keyframe()
+    }
+}
+
+/// Draws the changes on the screen, and adds them to the ones pending for the viewers. The draw
+/// is postponed while the program is in a synchronized update. Returns whether it was drawn.
+async fn draw<B: Backend>(
+    terminal: &mut Terminal<B>,
+    screen: &mut Pacer,
+    pending: &mut Frame,
+) -> Result<bool> {
+    if terminal.is_synchronized() {
+        screen.postpone();
+        return Ok(false);
+    }
+    pending.merge(terminal.draw().await?);
+    screen.done();
+    Ok(true)
+}
+
+/// Draws the border around the cast and the status line.
+async fn draw_status<B: Backend>(
+    terminal: &mut Terminal<B>,
+    status: &Status,
+    (width, height): (usize, usize),
+) -> Result<()> {
+    let cells = status.cells(&terminal.visible().0, width, height);
+    terminal.draw_around(cells).await?;
+    Ok(())
+}
+
+/// Limits how often something is done: changes are handled right away after a quiet period, and
+/// at most once per period otherwise. Nothing is scheduled while nothing changes.
+struct Pacer {
+    period: Duration,
+    last: Instant,
+    deadline: Option<Instant>,
+}
+
+impl Pacer {
+    fn new(fps: u32) -> Self {
+        let period = Duration::from_secs(1) / fps;
+        Self {
+            period,
+            last: Instant::now() - period,
+            deadline: None,
+        }
+    }
+
+    /// Returns whether a change can be handled now, or schedules it for the end of the period.
+    fn ready(&mut self) -> bool {
+        if self.deadline.is_none() && self.last.elapsed() >= self.period {
+            true
+        } else {
+            self.schedule();
+            false
+        }
+    }
+
+    fn schedule(&mut self) {
+        let next = std::cmp::max(self.last + self.period, Instant::now());
+        self.deadline.get_or_insert(next);
+    }
+
+    /// Schedules a change a full period from now, whenever the last one was handled.
+    fn postpone(&mut self) {
+        self.deadline = Some(Instant::now() + self.period);
+    }
+
+    fn is_scheduled(&self) -> bool {
+        self.deadline.is_some()
+    }
+
+    fn deadline(&self) -> Instant {
+        self.deadline.unwrap_or_else(Instant::now)
+    }
+
+    /// Called when the deadline is reached, the change being handled now.
+    fn fire(&mut self) {
+        self.deadline = None;
+    }
+
+    fn done(&mut self) {
+        self.last = Instant::now();
+        self.deadline = None;
+    }
+}
+
+/// Feeds a whole read from the pty to the parser.
+fn advance<B: Backend>(parser: &mut vte::Parser, terminal: &mut Terminal<B>, bytes: &[u8]) {
+    for byte in bytes {
+        parser.advance(terminal, *byte);
+    }
+}
+
+/// Asks the host terminal for its palette, so that the cast starts with its colors. The device
+/// attributes query, that every terminal answers, tells when all the answers were read.
+fn query_palette(stdout: &mut impl Write) -> Result<Vec<(PaletteIndex, Rgb)>> {
+    let stdin = stdin();
+    if !termion::is_tty(&stdin) {
+        return Ok(Vec::new());
+    }
+    write!(stdout, "{}\x1b[c", palette::queries())?;
+    stdout.flush()?;
+
+    let deadline = std::time::Instant::now() + PALETTE_QUERY_TIMEOUT;
+    let mut replies = Vec::new();
+    let mut buf = [0; 4096];
+    while !has_device_attributes(&replies) {
+        let timeout = deadline.saturating_duration_since(std::time::Instant::now());
+        let mut fds = [PollFd::new(stdin.as_raw_fd(), PollFlags::POLLIN)];
+        if poll(&mut fds, timeout.as_millis() as i32)? == 0 {
+            break;
+        }
+        match nix::unistd::read(stdin.as_raw_fd(), &mut buf)? {
+            0 => break,
+            n => replies.extend_from_slice(&buf[..n]),
         }
-        Ok(())
     }
+    Ok(palette::parse_replies(&replies))
+}
+
+/// Returns whether `bytes` has the answer to the device attributes query, `CSI ? ... c`.
+fn has_device_attributes(bytes: &[u8]) -> bool {
+    bytes
+        .windows(3)
+        .position(|w| w == b"\x1b[?")
+        .is_some_and(|start| bytes[start..].contains(&b'c'))
 }
 
-fn spawn_stdin() -> mpsc::UnboundedReceiver<u8> {
-    let (stdin_snd, stdin_recv) = mpsc::unbounded_channel();
-    tokio::task::spawn_blocking(move || {
+/// Reads stdin in chunks, so that escape sequences, such as mouse reports, are not split.
+///
+/// The queue is bounded, so that a paste faster than the pty can take blocks the reader instead
+/// of piling up in memory.
+///
+/// Stdin is polled, so that the reader stops once `stop` is set instead of taking what is typed
+/// next.
+/// Reads stdin on a thread, until the returned end of a pipe is closed, which wakes it up.
+fn spawn_stdin() -> Result<(mpsc::Receiver<Vec<u8>>, Option<File>)> {
+    let (stop_read, stop_write) = nix::unistd::pipe2(nix::fcntl::OFlag::O_CLOEXEC)?;
+    // owned, so that they are closed when dropped.
+    let (stopped, stop) = unsafe { (File::from_raw_fd(stop_read), File::from_raw_fd(stop_write)) };
+    let (stdin_snd, stdin_recv) = mpsc::channel(STDIN_QUEUE);
+    std::thread::spawn(move || {
         use std::io::Read;
         let mut stdin = stdin();
-        let mut buf = [0; 1];
+        let mut buf = [0; 4096];
         loop {
+            let mut fds = [
+                PollFd::new(stdin.as_raw_fd(), PollFlags::POLLIN),
+                PollFd::new(stopped.as_raw_fd(), PollFlags::POLLIN),
+            ];
+            let ready = poll(&mut fds, -1);
+            if fds[1].revents().is_some_and(|events| !events.is_empty()) {
+                break;
+            }
+            if !matches!(ready, Ok(n) if n > 0) {
+                continue;
+            }
             match stdin.read(&mut buf) {
-                Ok(1) => {
-                    if stdin_snd.send(buf[0] as u8).is_err() {
+                Ok(n) if n > 0 => {
+                    if stdin_snd.blocking_send(buf[..n].to_vec()).is_err() {
                         break;
                     }
                 }
@@ -154,5 +972,161 @@ fn spawn_stdin() -> mpsc::UnboundedReceiver<u8> {
             }
         }
     });
-    stdin_recv
+    Ok((stdin_recv, Some(stop)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clipboard::Clipboard;
+    use crate::shell::CommandEvent;
+
+    #[test]
+    fn lines_hidden_by_a_pause() {
+        let hidden = [10..20, 30..usize::MAX];
+        assert!(!is_hidden(&hidden, 0..10));
+        assert!(is_hidden(&hidden, 5..11));
+        assert!(is_hidden(&hidden, 19..20));
+        assert!(!is_hidden(&hidden, 20..30));
+        assert!(is_hidden(&hidden, 1000..1001));
+    }
+
+    #[test]
+    fn secret_input_read_from_the_master() {
+        use nix::sys::termios::{tcsetattr, SetArg};
+
+        let pty = nix::pty::openpty(None, None).unwrap();
+        let mut termios = tcgetattr(pty.slave).unwrap();
+        termios.local_flags |= LocalFlags::ICANON | LocalFlags::ECHO;
+        tcsetattr(pty.slave, SetArg::TCSANOW, &termios).unwrap();
+        assert!(!is_secret_input(pty.master));
+
+        // like `read -s`.
+        termios.local_flags.remove(LocalFlags::ECHO);
+        tcsetattr(pty.slave, SetArg::TCSANOW, &termios).unwrap();
+        assert!(is_secret_input(pty.master));
+
+        // a full screen program reads its keys without echo, and draws them itself.
+        termios.local_flags.remove(LocalFlags::ICANON);
+        tcsetattr(pty.slave, SetArg::TCSANOW, &termios).unwrap();
+        assert!(!is_secret_input(pty.master));
+
+        nix::unistd::close(pty.slave).unwrap();
+        nix::unistd::close(pty.master).unwrap();
+    }
+
+    #[test]
+    fn recording_starts_at_a_secret_prompt() {
+        let path = std::env::temp_dir().join(format!("termcast-test-{}.rec", std::process::id()));
+        let recorder = start_recording(&path, &Rect::new(0, 0, 2, 1), Frame::default(), true);
+        recorder.unwrap().finish().unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // the time, the length and the message.
+        let mut messages = Vec::new();
+        let mut rest = &bytes[..];
+        while !rest.is_empty() {
+            let len = u32::from_be_bytes([rest[8], rest[9], rest[10], rest[11]]) as usize;
+            messages.push(rest[12..12 + len].to_vec());
+            rest = &rest[12 + len..];
+        }
+        let tags: Vec<u8> = messages.iter().map(|message| message[0]).collect();
+        // hello, the first frame, and the secret input.
+        assert_eq!(tags, [0, 1, 2]);
+        assert_eq!(messages[2], [2, 1]);
+    }
+
+    #[test]
+    fn floor_granted_without_asking_only_if_allowed() {
+        let viewer = |permission| Viewer {
+            name: "viewer".to_string(),
+            addr: "10.0.0.1:4000".parse().unwrap(),
+            connected: std::time::Instant::now(),
+            permission,
+            stats: Arc::default(),
+        };
+        let full = viewer(Permission::Full);
+        assert!(is_auto_granted(true, &full, None));
+        assert!(!is_auto_granted(false, &full, None));
+        assert!(!is_auto_granted(true, &full, Some(2)));
+        assert!(!is_auto_granted(true, &viewer(Permission::RequestControl), None));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn pacer_holds_changes_until_the_end_of_the_period() {
+        let period = Duration::from_millis(100);
+        let mut pacer = Pacer::new(10);
+        assert!(pacer.ready());
+        pacer.done();
+
+        let start = Instant::now();
+        assert!(!pacer.ready());
+        assert!(pacer.is_scheduled());
+        assert_eq!(pacer.deadline(), start + period);
+        // a change already scheduled doesn't move the deadline.
+        tokio::time::advance(Duration::from_millis(30)).await;
+        assert!(!pacer.ready());
+        assert_eq!(pacer.deadline(), start + period);
+
+        tokio::time::advance(Duration::from_millis(70)).await;
+        pacer.fire();
+        assert!(!pacer.is_scheduled());
+        assert!(pacer.ready());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn pacer_postpones_by_a_full_period() {
+        let mut pacer = Pacer::new(10);
+        pacer.done();
+        tokio::time::advance(Duration::from_millis(500)).await;
+        pacer.postpone();
+        assert_eq!(pacer.deadline(), Instant::now() + Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn draw_waits_for_the_synchronized_update() {
+        let mut terminal = Terminal::new(Rect::new(0, 0, 4, 2), TermionBackend::new(Vec::new()));
+        let mut parser = vte::Parser::new();
+        let mut screen = Pacer::new(60);
+        let mut pending = Frame::default();
+
+        advance(&mut parser, &mut terminal, b"\x1b[?2026hab");
+        assert!(!draw(&mut terminal, &mut screen, &mut pending).await.unwrap());
+        assert!(screen.is_scheduled());
+        assert!(pending.is_empty());
+
+        advance(&mut parser, &mut terminal, b"\x1b[?2026l");
+        assert!(draw(&mut terminal, &mut screen, &mut pending).await.unwrap());
+        assert!(!screen.is_scheduled());
+        let symbols: String = pending.cells.iter().map(|(_, _, cell)| cell.symbol).collect();
+        assert_eq!(symbols, "ab");
+    }
+
+    #[tokio::test]
+    async fn keyframe_keeps_the_pending_events() {
+        let mut terminal = Terminal::new(Rect::new(0, 0, 4, 2), TermionBackend::new(Vec::new()));
+        let mut parser = vte::Parser::new();
+        let mut screen = Pacer::new(60);
+        let mut pending = Frame::default();
+        advance(&mut parser, &mut terminal, b"ab");
+        draw(&mut terminal, &mut screen, &mut pending).await.unwrap();
+        let command = CommandEvent {
+            command: "ls".to_string(),
+            ..CommandEvent::default()
+        };
+        let clipboard = Clipboard {
+            selection: "c".to_string(),
+            content: "aGk=".to_string(),
+        };
+        pending.commands.push(command.clone());
+        pending.clipboard = Some(clipboard.clone());
+
+        let frame = keyframe(&terminal, &mut pending);
+        assert!(pending.is_empty());
+        assert_eq!(frame.cells.len(), 8);
+        assert_eq!(frame.commands, [command]);
+        assert_eq!(frame.clipboard, Some(clipboard));
+    }
 }
+
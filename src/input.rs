@@ -0,0 +1,153 @@
+use std::convert::TryFrom;
+
+use crate::layout::Rect;
+
+const ESC: u8 = 0x1b;
+
+/// Translates the mouse reports of the host terminal from host screen coordinates to cast
+/// coordinates, dropping the ones that fall outside of the part of the cast that is shown.
+#[derive(Default)]
+pub struct MouseTranslator {
+    /// start of a mouse report that was split between two reads.
+    pending: Vec<u8>,
+}
+
+impl MouseTranslator {
+    /// `rect` is where the cast is shown on the host screen, and `origin` the cell of the cast
+    /// at its top left corner.
+    pub fn translate(&mut self, input: &[u8], rect: &Rect, origin: (usize, usize)) -> Vec<u8> {
+        let mut input = {
+            let mut pending = std::mem::take(&mut self.pending);
+            pending.extend_from_slice(input);
+            pending
+        };
+        let mut output = Vec::with_capacity(input.len());
+        let mut i = 0;
+        while i < input.len() {
+            let rest = &input[i..];
+            if rest.starts_with(&[ESC, b'[', b'<']) {
+                match parse_sgr(rest) {
+                    Some((len, report)) => {
+                        if let Some(report) = report.translate(rect, origin) {
+                            output.extend_from_slice(&report);
+                        }
+                        i += len;
+                        continue;
+                    }
+                    None if rest[3..].iter().all(|&b| b.is_ascii_digit() || b == b';') => {
+                        self.pending = input.split_off(i);
+                        break;
+                    }
+                    None => (),
+                }
+            } else if rest.starts_with(&[ESC, b'[', b'M']) {
+                if rest.len() < 6 {
+                    self.pending = input.split_off(i);
+                    break;
+                }
+                let report = MouseReport {
+                    button: rest[3].wrapping_sub(32) as usize,
+                    x: rest[4].wrapping_sub(32) as usize,
+                    y: rest[5].wrapping_sub(32) as usize,
+                    sgr_final: None,
+                };
+                if let Some(report) = report.translate(rect, origin) {
+                    output.extend_from_slice(&report);
+                }
+                i += 6;
+                continue;
+            }
+            output.push(input[i]);
+            i += 1;
+        }
+        output
+    }
+}
+
+struct MouseReport {
+    button: usize,
+    /// 1-based
+    x: usize,
+    /// 1-based
+    y: usize,
+    /// `M` or `m` for SGR reports, `None` for X10 reports.
+    sgr_final: Option<u8>,
+}
+
+impl MouseReport {
+    /// Moves the report to the cast coordinates and encodes it back, or returns `None` if it is
+    /// outside of the cast.
+    fn translate(&self, rect: &Rect, (ox, oy): (usize, usize)) -> Option<Vec<u8>> {
+        let x = self.x.checked_sub(rect.x).filter(|&x| x >= 1 && x <= rect.width)? + ox;
+        let y = self.y.checked_sub(rect.y).filter(|&y| y >= 1 && y <= rect.height)? + oy;
+        match self.sgr_final {
+            Some(c) => Some(format!("\x1b[<{};{};{}{}", self.button, x, y, c as char).into_bytes()),
+            None => {
+                // X10 encoding can't represent coordinates above 223.
+                let encode = |v: usize| u8::try_from(v + 32).ok();
+                Some(vec![
+                    ESC,
+                    b'[',
+                    b'M',
+                    (self.button as u8).wrapping_add(32),
+                    encode(x)?,
+                    encode(y)?,
+                ])
+            }
+        }
+    }
+}
+
+/// Parses a `ESC [ < b ; x ; y M` report, returning its length.
+fn parse_sgr(input: &[u8]) -> Option<(usize, MouseReport)> {
+    let end = input.iter().position(|&b| b == b'M' || b == b'm')?;
+    let params = std::str::from_utf8(&input[3..end]).ok()?;
+    let mut params = params.split(';').map(str::parse::<usize>);
+    let report = MouseReport {
+        button: params.next()?.ok()?,
+        x: params.next()?.ok()?,
+        y: params.next()?.ok()?,
+        sgr_final: Some(input[end]),
+    };
+    Some((end + 1, report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a 10x5 cast shown at column 3, row 2 of the host screen.
+    fn rect() -> Rect {
+        Rect::new(2, 1, 10, 5)
+    }
+
+    #[test]
+    fn sgr_reports_moved_to_the_cast() {
+        let mut mouse = MouseTranslator::default();
+        let output = mouse.translate(b"a\x1b[<0;3;2Mb\x1b[<0;12;6m", &rect(), (0, 0));
+        assert_eq!(output, b"a\x1b[<0;1;1Mb\x1b[<0;10;5m");
+    }
+
+    #[test]
+    fn reports_outside_the_cast_are_dropped() {
+        let mut mouse = MouseTranslator::default();
+        assert_eq!(mouse.translate(b"\x1b[<0;2;2M\x1b[<0;13;2M", &rect(), (0, 0)), b"");
+        assert_eq!(mouse.translate(b"\x1b[M !\"", &rect(), (0, 0)), b"");
+    }
+
+    #[test]
+    fn x10_reports_with_a_viewport() {
+        let mut mouse = MouseTranslator::default();
+        let output = mouse.translate(b"\x1b[M #$", &rect(), (20, 30));
+        assert_eq!(output, b"\x1b[M 5A");
+    }
+
+    #[test]
+    fn split_reports_are_joined() {
+        let mut mouse = MouseTranslator::default();
+        assert_eq!(mouse.translate(b"x\x1b[<0;3", &rect(), (0, 0)), b"x");
+        assert_eq!(mouse.translate(b";2M", &rect(), (0, 0)), b"\x1b[<0;1;1M");
+        assert_eq!(mouse.translate(b"\x1b[M ", &rect(), (0, 0)), b"");
+        assert_eq!(mouse.translate(b"#$", &rect(), (0, 0)), b"\x1b[M !#");
+    }
+}
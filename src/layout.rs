@@ -1,3 +1,5 @@
+use std::str::FromStr;
+
 #[derive(Debug, Clone)]
 pub struct Rect {
     pub x: usize,
@@ -9,5 +11,43 @@ pub struct Rect {
 impl Rect {
     pub fn new(x: usize, y: usize, width: usize, height: usize) -> Rect {
         Rect { x, y, width, height }
-    } 
+    }
+}
+
+/// What to do when the cast is bigger than the host terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Fit {
+    /// Refuse to start.
+    #[default]
+    Error,
+    /// Make the cast as big as the host terminal.
+    Shrink,
+    /// Show the part of the cast around the cursor.
+    Viewport,
+}
+
+impl FromStr for Fit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(Fit::Error),
+            "shrink" => Ok(Fit::Shrink),
+            "viewport" => Ok(Fit::Viewport),
+            other => Err(format!("invalid fit mode: {}", other)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_fit() {
+        assert_eq!("error".parse(), Ok(Fit::Error));
+        assert_eq!("shrink".parse(), Ok(Fit::Shrink));
+        assert_eq!("viewport".parse(), Ok(Fit::Viewport));
+        assert!("scroll".parse::<Fit>().is_err());
+    }
 }
@@ -0,0 +1,108 @@
+#[macro_use]
+extern crate bitflags;
+
+pub mod backends;
+mod buffer;
+mod cell;
+mod clipboard;
+mod command;
+mod frame;
+pub mod guard;
+pub mod host;
+mod input;
+pub mod layout;
+mod mode;
+pub mod network;
+mod palette;
+mod record;
+mod redact;
+mod scrollback;
+mod shell;
+mod status;
+mod style;
+pub mod terminal;
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use ipnet::IpNet;
+use regex::Regex;
+use structopt::StructOpt;
+
+use clipboard::ClipboardPolicy;
+use command::PrefixKey;
+use layout::Fit;
+use network::access::parse_net;
+use network::Permission;
+
+#[derive(StructOpt)]
+pub struct CastOptions {
+    #[structopt(short = "r", default_value = "40")]
+    rows: usize,
+    #[structopt(short = "c", default_value = "80")]
+    cols: usize,
+    /// What to do with clipboard copies (OSC 52) from the casted program.
+    #[structopt(long = "clipboard", default_value = "forward", possible_values = &["forward", "deny", "share"])]
+    clipboard: ClipboardPolicy,
+    /// Let the casted program read the host's clipboard.
+    #[structopt(long = "allow-clipboard-read")]
+    allow_clipboard_read: bool,
+    /// What to do when the cast doesn't fit in the terminal: refuse to start, shrink the cast,
+    /// or show the part of it around the cursor.
+    #[structopt(long = "fit", default_value = "error", possible_values = &["error", "shrink", "viewport"])]
+    fit: Fit,
+    /// Maximum number of frames drawn per second on the host's screen.
+    #[structopt(long = "fps", default_value = "60")]
+    fps: u32,
+    /// Maximum number of frames sent per second to the viewers, defaults to `--fps`.
+    #[structopt(long = "viewer-fps")]
+    viewer_fps: Option<u32>,
+    /// Key that starts a termcast command, such as pausing the broadcast or quitting.
+    #[structopt(long = "prefix-key", default_value = "C-]")]
+    prefix_key: PrefixKey,
+    /// File where the session is recorded when recording is turned on, defaults to
+    /// `termcast-<timestamp>.rec`.
+    #[structopt(long = "record", parse(from_os_str))]
+    record: Option<PathBuf>,
+    /// Regex of text hidden from the viewers and the recordings, on top of the common secrets
+    /// such as AWS keys or `password=` lines. Can be repeated.
+    #[structopt(long = "redact", number_of_values = 1)]
+    redact: Vec<Regex>,
+    /// Don't hide the common secrets, only the `--redact` patterns.
+    #[structopt(long = "no-default-redaction")]
+    no_default_redaction: bool,
+    /// Address the viewers connect to. Port 0 picks a free one, shown in the status line.
+    #[structopt(long = "listen", default_value = "0.0.0.0:9999")]
+    listen: SocketAddr,
+    /// Token that lets a viewer in, instead of a passphrase generated for the cast. Can be
+    /// repeated, to give each viewer their own.
+    #[structopt(long = "token", number_of_values = 1)]
+    tokens: Vec<String>,
+    /// Encrypt the connections with TLS, with a certificate generated for the cast unless
+    /// `--cert` is given. Viewers can pin its fingerprint, shown at start.
+    #[structopt(long = "tls")]
+    tls: bool,
+    /// PEM file of the TLS certificate chain, turns TLS on.
+    #[structopt(long = "cert", parse(from_os_str), requires = "key")]
+    cert: Option<PathBuf>,
+    /// PEM file of the private key of `--cert`.
+    #[structopt(long = "key", parse(from_os_str), requires = "cert")]
+    key: Option<PathBuf>,
+    /// What viewers can do when they connect. The host can change it for each viewer, and give a
+    /// viewer control, or take it back, at any time.
+    #[structopt(long = "viewer-permission", default_value = "read-only", possible_values = &["read-only", "request-control", "full"])]
+    viewer_permission: Permission,
+    /// Let a viewer with the `full` permission take the floor when nobody has it, without asking.
+    #[structopt(long = "auto-grant")]
+    auto_grant: bool,
+    /// Maximum number of viewers, not counting the ones still connecting.
+    #[structopt(long = "max-viewers")]
+    max_viewers: Option<usize>,
+    /// Range of addresses viewers can connect from, such as `10.0.0.0/8` or a single address.
+    /// Can be repeated. Any address can connect if there is none.
+    #[structopt(long = "allow", number_of_values = 1, parse(try_from_str = parse_net))]
+    allow: Vec<IpNet>,
+    /// Range of addresses refused, even if they are in an `--allow` range. Can be repeated.
+    #[structopt(long = "deny", number_of_values = 1, parse(try_from_str = parse_net))]
+    deny: Vec<IpNet>,
+}
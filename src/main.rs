@@ -1,18 +1,8 @@
-#[macro_use]
-extern crate bitflags;
-
-mod backends;
-mod buffer;
-mod cell;
-mod host;
-mod layout;
-mod network;
-mod style;
-mod terminal;
-
 use structopt::StructOpt;
 use anyhow::Result;
 
+use termcast::{guard, host, CastOptions};
+
 #[derive(StructOpt)]
 struct Options {
     #[structopt(short = "d", long = "debug")]
@@ -23,22 +13,36 @@ struct Options {
 
 #[derive(StructOpt)]
 enum Command {
-    Cast {
-        #[structopt(short = "r", default_value = "40")]
-        rows: usize,
-        #[structopt(short = "c", default_value = "80")]
-        cols: usize,
-    },
+    Cast(CastOptions),
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
+fn main() -> Result<()> {
     let opt = Options::from_args();
     if opt.debug {
         env_logger::init();
     }
     match opt.command {
-        Command::Cast { rows, cols } => host::Host::new(cols, rows).await?.run().await?,
+        Command::Cast(options) => {
+            // before the runtime starts its threads.
+            let detach = guard::Detach::fork()?;
+            tokio::runtime::Runtime::new()?.block_on(cast(options, detach))?;
+        }
+    }
+    Ok(())
+}
+
+async fn cast(options: CastOptions, detach: guard::Detach) -> Result<()> {
+    let guard = guard::TerminalGuard::new()?;
+    let result = async {
+        let mut host = host::Host::new(&options).await?;
+        host.set_detach(detach);
+        host.run().await
+    }
+    .await;
+    match result {
+        Ok(ref reason) => guard.finish(&reason.to_string()),
+        Err(_) => guard.finish("error"),
     }
+    result?;
     Ok(())
 }
@@ -0,0 +1,55 @@
+bitflags! {
+    /// Private modes (DECSET/DECRST) of the casted program that are mirrored on the host terminal.
+    #[derive(Default)]
+    pub struct Mode: u8 {
+        const MOUSE_CLICK       = 0b0000_0001;
+        const MOUSE_DRAG        = 0b0000_0010;
+        const MOUSE_MOTION      = 0b0000_0100;
+        const SGR_MOUSE         = 0b0000_1000;
+        const FOCUS_EVENTS      = 0b0001_0000;
+        const BRACKETED_PASTE   = 0b0010_0000;
+
+        const MOUSE = Self::MOUSE_CLICK.bits | Self::MOUSE_DRAG.bits | Self::MOUSE_MOTION.bits;
+    }
+}
+
+impl Mode {
+    /// (flag, DECSET number) pairs.
+    const NUMBERS: [(Mode, u16); 6] = [
+        (Mode::MOUSE_CLICK, 1000),
+        (Mode::MOUSE_DRAG, 1002),
+        (Mode::MOUSE_MOTION, 1003),
+        (Mode::FOCUS_EVENTS, 1004),
+        (Mode::SGR_MOUSE, 1006),
+        (Mode::BRACKETED_PASTE, 2004),
+    ];
+
+    pub fn from_number(number: u16) -> Option<Mode> {
+        Self::NUMBERS
+            .iter()
+            .find(|(_, n)| *n == number)
+            .map(|(mode, _)| *mode)
+    }
+
+    /// The DECSET numbers of the modes set in `self`.
+    pub fn numbers(self) -> impl Iterator<Item = u16> {
+        Self::NUMBERS
+            .iter()
+            .filter(move |(mode, _)| self.contains(*mode))
+            .map(|(_, n)| *n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decset_numbers() {
+        assert_eq!(Mode::from_number(1006), Some(Mode::SGR_MOUSE));
+        assert_eq!(Mode::from_number(2004), Some(Mode::BRACKETED_PASTE));
+        assert_eq!(Mode::from_number(25), None);
+        let mode = Mode::MOUSE_CLICK | Mode::FOCUS_EVENTS;
+        assert_eq!(mode.numbers().collect::<Vec<_>>(), [1000, 1004]);
+    }
+}
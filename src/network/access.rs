@@ -0,0 +1,209 @@
+//! Which connections are accepted.
+
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use ipnet::IpNet;
+
+use super::auth::{Auth, Limiter};
+
+/// Most connections an address can have in the handshake at once.
+const MAX_PENDING_PER_ADDR: usize = 4;
+/// Most connections in the handshake at once, from any address.
+const MAX_PENDING: usize = 64;
+
+/// Something the host banned for the rest of the cast.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Ban {
+    Addr(IpAddr),
+    /// Only known once the viewer is authenticated.
+    Name(String),
+}
+
+/// The connections open when a new one comes.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Connections {
+    /// authenticated viewers.
+    pub viewers: usize,
+    /// connections still in the handshake.
+    pub pending: usize,
+    /// connections still in the handshake, from the same address as the new one.
+    pub pending_from_addr: usize,
+}
+
+/// What a connection must get through before and during the handshake.
+pub struct Access {
+    pub auth: Arc<Auth>,
+    /// ranges the connections may come from, any if empty.
+    allow: Vec<IpNet>,
+    /// ranges refused, even if they are allowed.
+    deny: Vec<IpNet>,
+    /// not counting the connections still in the handshake.
+    max_viewers: Option<usize>,
+    banned_addrs: HashSet<IpAddr>,
+    banned_names: HashSet<String>,
+    limiter: Limiter,
+}
+
+impl Access {
+    pub fn new(
+        auth: Auth,
+        allow: Vec<IpNet>,
+        deny: Vec<IpNet>,
+        max_viewers: Option<usize>,
+    ) -> Self {
+        Self {
+            auth: Arc::new(auth),
+            allow,
+            deny,
+            max_viewers,
+            banned_addrs: HashSet::new(),
+            banned_names: HashSet::new(),
+            limiter: Limiter::default(),
+        }
+    }
+
+    /// Returns why a connection from `addr` is refused, `connections` being already open.
+    pub fn check(&mut self, addr: IpAddr, connections: Connections) -> Result<(), &'static str> {
+        if self.banned_addrs.contains(&addr) {
+            Err("banned")
+        } else if self.deny.iter().any(|net| net.contains(&addr)) {
+            Err("address denied")
+        } else if !self.allow.is_empty() && !self.allow.iter().any(|net| net.contains(&addr)) {
+            Err("address not allowed")
+        } else if !self.limiter.is_allowed(addr) {
+            Err("too many failed attempts")
+        } else if self.is_full(connections.viewers) {
+            Err("too many viewers")
+        } else if connections.pending_from_addr >= MAX_PENDING_PER_ADDR
+            || connections.pending >= MAX_PENDING
+        {
+            Err("too many connections")
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns why an authenticated viewer is refused, `viewers` being already in.
+    pub fn admit(&self, addr: IpAddr, name: &str, viewers: usize) -> Result<(), String> {
+        if self.is_banned(addr, Some(name)) {
+            Err(format!("{} is banned", name))
+        } else if self.is_full(viewers) {
+            Err("too many viewers".to_string())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn is_full(&self, viewers: usize) -> bool {
+        self.max_viewers.is_some_and(|max| viewers >= max)
+    }
+
+    /// Remembers a failed authentication from `addr`.
+    pub fn fail(&mut self, addr: IpAddr) {
+        self.limiter.fail(addr);
+    }
+
+    pub fn ban(&mut self, ban: Ban) {
+        match ban {
+            Ban::Addr(addr) => self.banned_addrs.insert(addr),
+            Ban::Name(name) => self.banned_names.insert(name),
+        };
+    }
+
+    /// Returns whether a viewer from `addr`, with `name` if it is authenticated, is banned.
+    pub fn is_banned(&self, addr: IpAddr, name: Option<&str>) -> bool {
+        self.banned_addrs.contains(&addr)
+            || name.is_some_and(|name| self.banned_names.contains(name))
+    }
+}
+
+/// Parses a range of addresses, in CIDR notation, or a single address.
+pub fn parse_net(s: &str) -> Result<IpNet, String> {
+    s.parse()
+        .or_else(|_| s.parse::<IpAddr>().map(IpNet::from))
+        .map_err(|_| format!("invalid address range: {}", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn access(allow: &[&str], deny: &[&str], max_viewers: Option<usize>) -> Access {
+        let nets = |nets: &[&str]| nets.iter().map(|net| parse_net(net).unwrap()).collect();
+        let auth = Auth::Tokens(vec!["token".to_string()]);
+        Access::new(auth, nets(allow), nets(deny), max_viewers)
+    }
+
+    fn addr(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_ranges() {
+        assert_eq!(parse_net("10.0.0.0/8"), Ok("10.0.0.0/8".parse().unwrap()));
+        assert_eq!(parse_net("10.1.2.3"), Ok("10.1.2.3/32".parse().unwrap()));
+        assert_eq!(parse_net("::1"), Ok("::1/128".parse().unwrap()));
+        assert!(parse_net("10.0.0.0/33").is_err());
+        assert!(parse_net("localhost").is_err());
+    }
+
+    #[test]
+    fn deny_wins_over_allow() {
+        let mut access = access(&["10.0.0.0/8"], &["10.0.0.0/24"], None);
+        let none = Connections::default();
+        assert_eq!(access.check(addr("10.1.0.1"), none), Ok(()));
+        assert_eq!(access.check(addr("10.0.0.1"), none), Err("address denied"));
+        assert_eq!(access.check(addr("192.168.0.1"), none), Err("address not allowed"));
+
+        access.ban(Ban::Addr(addr("10.1.0.1")));
+        assert_eq!(access.check(addr("10.1.0.1"), none), Err("banned"));
+    }
+
+    #[test]
+    fn only_viewers_count_toward_the_maximum() {
+        let mut access = access(&[], &[], Some(2));
+        let addr = addr("10.0.0.1");
+        let connections = Connections {
+            viewers: 1,
+            pending: 3,
+            pending_from_addr: 0,
+        };
+        assert_eq!(access.check(addr, connections), Ok(()));
+        assert_eq!(access.admit(addr, "bob", 1), Ok(()));
+
+        let full = Connections { viewers: 2, ..connections };
+        assert_eq!(access.check(addr, full), Err("too many viewers"));
+        assert_eq!(access.admit(addr, "bob", 2), Err("too many viewers".to_string()));
+    }
+
+    #[test]
+    fn pending_handshakes_are_capped() {
+        let mut access = access(&[], &[], None);
+        let addr = addr("10.0.0.1");
+        let from_addr = Connections {
+            pending: MAX_PENDING_PER_ADDR,
+            pending_from_addr: MAX_PENDING_PER_ADDR,
+            ..Connections::default()
+        };
+        assert_eq!(access.check(addr, from_addr), Err("too many connections"));
+        let from_others = Connections {
+            pending: MAX_PENDING,
+            ..Connections::default()
+        };
+        assert_eq!(access.check(addr, from_others), Err("too many connections"));
+    }
+
+    #[test]
+    fn names_are_banned_once_authenticated() {
+        let mut access = access(&[], &[], None);
+        let addr = addr("10.0.0.1");
+        access.ban(Ban::Name("mallory".to_string()));
+        assert_eq!(access.check(addr, Connections::default()), Ok(()));
+        assert_eq!(access.admit(addr, "mallory", 0), Err("mallory is banned".to_string()));
+        assert_eq!(access.admit(addr, "alice", 0), Ok(()));
+        assert!(access.is_banned(addr, Some("mallory")));
+        assert!(!access.is_banned(addr, None));
+    }
+}
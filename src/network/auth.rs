@@ -0,0 +1,180 @@
+//! Authentication of the viewers.
+//!
+//! The host sends a random nonce, and the viewer answers with the HMAC-SHA256 of the nonce keyed
+//! with its secret, so that the secret is never sent.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use hmac::{Hmac, Mac, NewMac};
+use sha2::Sha256;
+
+pub const NONCE_LEN: usize = 32;
+pub const MAC_LEN: usize = 32;
+
+/// Number of failed attempts from an address before it is refused.
+const MAX_FAILURES: usize = 5;
+/// How long failed attempts are remembered.
+const FAILURE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Letters of the generated passphrases, without the ones that are easily mistaken for others.
+const PASSPHRASE_ALPHABET: &[u8] = b"abcdefghijkmnopqrstuvwxyz23456789";
+/// Number of letters of the generated passphrases, about 100 bits.
+const PASSPHRASE_LEN: usize = 20;
+
+/// The secrets that let a viewer in.
+pub enum Auth {
+    /// Generated at the start of the cast, for the host to give to the viewers.
+    Passphrase(String),
+    /// Given on the command line, one per viewer or group of viewers.
+    Tokens(Vec<String>),
+}
+
+impl Auth {
+    /// Uses the tokens, or generates a passphrase if there are none.
+    pub fn new(tokens: Vec<String>) -> anyhow::Result<Self> {
+        if !tokens.is_empty() {
+            return Ok(Auth::Tokens(tokens));
+        }
+        // bytes past the last multiple of the alphabet's length would make some letters more
+        // likely.
+        let limit = 256 - 256 % PASSPHRASE_ALPHABET.len();
+        let mut letters = Vec::new();
+        while letters.len() < PASSPHRASE_LEN {
+            let mut bytes = [0; 32];
+            getrandom::getrandom(&mut bytes)?;
+            letters.extend(
+                bytes
+                    .iter()
+                    .filter(|&&byte| (byte as usize) < limit)
+                    .map(|&byte| PASSPHRASE_ALPHABET[byte as usize % PASSPHRASE_ALPHABET.len()]),
+            );
+        }
+        let groups: Vec<_> = letters[..PASSPHRASE_LEN]
+            .chunks(5)
+            .map(|group| String::from_utf8_lossy(group).into_owned())
+            .collect();
+        let passphrase = groups.join("-");
+        Ok(Auth::Passphrase(passphrase))
+    }
+
+    pub fn nonce() -> anyhow::Result<[u8; NONCE_LEN]> {
+        let mut nonce = [0; NONCE_LEN];
+        getrandom::getrandom(&mut nonce)?;
+        Ok(nonce)
+    }
+
+    /// Returns which secret `mac` was made with, the tokens being numbered from 1, and 0 being
+    /// the passphrase.
+    pub fn verify(&self, nonce: &[u8], mac: &[u8]) -> Option<usize> {
+        let check = |secret: &str| {
+            let mut expected =
+                Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("any key length works");
+            expected.update(nonce);
+            expected.verify(mac).is_ok()
+        };
+        match self {
+            Auth::Passphrase(passphrase) => Some(0).filter(|_| check(passphrase)),
+            Auth::Tokens(tokens) => tokens.iter().position(|token| check(token)).map(|i| i + 1),
+        }
+    }
+}
+
+impl fmt::Display for Auth {
+    /// What the host is shown, to tell the viewers how to connect.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Auth::Passphrase(passphrase) => write!(f, "passphrase: {}", passphrase),
+            Auth::Tokens(tokens) => write!(f, "viewers need one of the {} tokens", tokens.len()),
+        }
+    }
+}
+
+/// Remembers the failed attempts of each address, to refuse the ones trying too many secrets.
+#[derive(Default)]
+pub struct Limiter {
+    failures: HashMap<IpAddr, Vec<Instant>>,
+}
+
+impl Limiter {
+    pub fn fail(&mut self, addr: IpAddr) {
+        self.failures.entry(addr).or_default().push(Instant::now());
+    }
+
+    pub fn is_allowed(&mut self, addr: IpAddr) -> bool {
+        self.failures.retain(|_, failures| {
+            failures.retain(|at| at.elapsed() < FAILURE_WINDOW);
+            !failures.is_empty()
+        });
+        self.failures
+            .get(&addr)
+            .is_none_or(|failures| failures.len() < MAX_FAILURES)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mac(secret: &str, nonce: &[u8]) -> Vec<u8> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(nonce);
+        mac.finalize().into_bytes().to_vec()
+    }
+
+    #[test]
+    fn passphrase_format() {
+        let passphrase = match Auth::new(Vec::new()).unwrap() {
+            Auth::Passphrase(passphrase) => passphrase,
+            Auth::Tokens(_) => panic!("no tokens were given"),
+        };
+        let groups: Vec<_> = passphrase.split('-').collect();
+        assert_eq!(groups.len(), 4);
+        assert!(groups.iter().all(|group| group.len() == 5));
+        assert!(groups.concat().bytes().all(|b| PASSPHRASE_ALPHABET.contains(&b)));
+    }
+
+    #[test]
+    fn verify_passphrase() {
+        let auth = Auth::Passphrase("abcde-fghij".to_string());
+        let nonce = Auth::nonce().unwrap();
+        assert_eq!(auth.verify(&nonce, &mac("abcde-fghij", &nonce)), Some(0));
+        assert_eq!(auth.verify(&nonce, &mac("abcde-fghik", &nonce)), None);
+        // an answer to another challenge.
+        assert_eq!(auth.verify(&nonce, &mac("abcde-fghij", &[0; NONCE_LEN])), None);
+    }
+
+    #[test]
+    fn verify_tokens() {
+        let auth = Auth::Tokens(vec!["alice".to_string(), "bob".to_string()]);
+        let nonce = Auth::nonce().unwrap();
+        assert_eq!(auth.verify(&nonce, &mac("bob", &nonce)), Some(2));
+        assert_eq!(auth.verify(&nonce, &mac("carol", &nonce)), None);
+        assert_eq!(auth.verify(&nonce, &[]), None);
+    }
+
+    #[test]
+    fn limiter_refuses_after_too_many_failures() {
+        let mut limiter = Limiter::default();
+        let (addr, other) = ("10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap());
+        for _ in 0..MAX_FAILURES - 1 {
+            limiter.fail(addr);
+        }
+        assert!(limiter.is_allowed(addr));
+        limiter.fail(addr);
+        assert!(!limiter.is_allowed(addr));
+        assert!(limiter.is_allowed(other));
+    }
+
+    #[test]
+    fn limiter_forgets_old_failures() {
+        let mut limiter = Limiter::default();
+        let addr = "10.0.0.1".parse().unwrap();
+        let old = Instant::now() - FAILURE_WINDOW;
+        limiter.failures.insert(addr, vec![old; MAX_FAILURES]);
+        assert!(limiter.is_allowed(addr));
+        assert!(limiter.failures.is_empty());
+    }
+}
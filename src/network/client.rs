@@ -1,44 +1,210 @@
-use crate::buffer::Buffer;
-use crate::cell::Cell;
-use crate::layout::Rect;
+use std::sync::Arc;
+use std::time::Duration;
 
+use anyhow::{anyhow, bail, ensure};
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::io::{split, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::net::TcpStream;
-use tokio::sync::broadcast;
-use tokio::io::AsyncWriteExt;
+use tokio::time::timeout;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
 use log::error;
 
-#[allow(dead_code)]
-pub struct Client {
-    stream: TcpStream,
-    receiver: broadcast::Receiver<Vec<(usize, usize, Cell)>>,
-    buffer: Buffer,
+use super::auth::Auth;
+use super::protocol::{Message, Request, MAX_AUTH_REQUEST_LEN, MAX_REQUEST_LEN};
+use super::registry::Stats;
+use super::Update;
+
+/// How long a viewer has for each step of the handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A viewer, over a TCP or a TLS stream.
+pub struct Client<S> {
+    id: usize,
+    stream: S,
+    /// subscribed to once the viewer is authenticated.
+    sender: broadcast::Sender<Arc<[u8]>>,
+    hello: Arc<[u8]>,
+    auth: Arc<Auth>,
+    /// messages for this viewer only.
+    direct: mpsc::UnboundedReceiver<Arc<[u8]>>,
+    stats: Arc<Stats>,
+}
+
+impl Client<TcpStream> {
+    /// Runs the TLS handshake, the rest of the exchange being encrypted.
+    pub async fn encrypt(self, tls: &TlsAcceptor) -> anyhow::Result<Client<TlsStream<TcpStream>>> {
+        let stream = match timeout(HANDSHAKE_TIMEOUT, tls.accept(self.stream)).await {
+            Ok(stream) => stream.map_err(|e| anyhow!("TLS handshake failed: {}", e))?,
+            Err(_) => bail!("TLS handshake timed out"),
+        };
+        Ok(Client {
+            id: self.id,
+            stream,
+            sender: self.sender,
+            hello: self.hello,
+            auth: self.auth,
+            direct: self.direct,
+            stats: self.stats,
+        })
+    }
 }
 
-impl Client {
+impl<S: AsyncRead + AsyncWrite + Unpin> Client<S> {
     pub fn new(
-        stream: TcpStream,
-        receiver: broadcast::Receiver<Vec<(usize, usize, Cell)>>,
+        id: usize,
+        stream: S,
+        sender: broadcast::Sender<Arc<[u8]>>,
+        hello: Arc<[u8]>,
+        auth: Arc<Auth>,
+        direct: mpsc::UnboundedReceiver<Arc<[u8]>>,
+        stats: Arc<Stats>,
     ) -> Self {
-        let buffer = Buffer::new(Rect::new(0, 0, 80, 40));
         Self {
+            id,
             stream,
-            receiver,
-            buffer,
+            sender,
+            hello,
+            auth,
+            direct,
+            stats,
         }
     }
 
-    pub async fn run(mut self) -> anyhow::Result<()> {
-        loop {
-            match self.receiver.recv().await {
-                Ok(_data) => {
-                    self.stream.write_all(b"hello").await?;
+    pub async fn run(mut self, updates: mpsc::UnboundedSender<Update>) -> anyhow::Result<()> {
+        let id = self.id;
+        let (secret, name) = match self.authenticate().await {
+            Ok(authenticated) => authenticated,
+            Err(reason) => {
+                let denied = Message::Denied(reason.to_string()).encode();
+                let _ = self.stream.write_all(&denied).await;
+                let _ = updates.send(Update::Rejected { id, reason: reason.to_string() });
+                return Ok(());
+            }
+        };
+        // subscribed before the host is told, so that the keyframe it sends then isn't missed.
+        let mut receiver = self.sender.subscribe();
+        let (admitted, admission) = oneshot::channel();
+        let _ = updates.send(Update::Authenticated { id, secret, name, admitted });
+        match admission.await {
+            Ok(Ok(())) => {}
+            Ok(Err(reason)) => {
+                drop(receiver);
+                let _ = self.stream.write_all(&Message::Denied(reason).encode()).await;
+                return Ok(());
+            }
+            Err(_) => bail!("the network stopped"),
+        }
+
+        let (mut reader, mut writer) = split(self.stream);
+        writer.write_all(&self.hello).await?;
+        let stats = self.stats;
+        stats.sent(self.hello.len());
+        let mut direct = self.direct;
+        let read = async {
+            loop {
+                match read_request(&mut reader, MAX_REQUEST_LEN).await? {
+                    Request::Auth { .. } => bail!("already authenticated"),
+                    Request::Input(input) => {
+                        let _ = updates.send(Update::Input { id, input });
+                    }
+                    Request::AskControl => {
+                        let _ = updates.send(Update::AskControl { id });
+                    }
+                    Request::GetLines { start, count } => {
+                        let _ = updates.send(Update::GetLines { id, start, count });
+                    }
                 }
-                Err(e) => {
-                    error!("client error: {}", e);
-                    break
-                },
             }
+        };
+        let write = async {
+            loop {
+                let message = tokio::select! {
+                    message = receiver.recv() => match message {
+                        Ok(message) => message,
+                        // the frames it missed are drawn again by the keyframe the host sends.
+                        Err(broadcast::error::RecvError::Lagged(missed)) => {
+                            stats.lag(missed);
+                            let _ = updates.send(Update::Lagged { id });
+                            continue
+                        }
+                        Err(e) => {
+                            error!("client error: {}", e);
+                            break
+                        },
+                    },
+                    Some(message) = direct.recv() => message,
+                };
+                writer.write_all(&message).await?;
+                stats.sent(message.len());
+            }
+            Ok(())
+        };
+        tokio::select! {
+            result = read => result,
+            result = write => result,
+        }
+    }
+
+    /// Sends a challenge to the viewer, and returns which secret it answered with, and its name.
+    async fn authenticate(&mut self) -> anyhow::Result<(usize, String)> {
+        let nonce = Auth::nonce()?;
+        self.stream.write_all(&Message::Challenge(nonce).encode()).await?;
+        let request = read_request(&mut self.stream, MAX_AUTH_REQUEST_LEN);
+        let request = match timeout(HANDSHAKE_TIMEOUT, request).await {
+            Ok(request) => request?,
+            Err(_) => bail!("authentication timed out"),
+        };
+        match request {
+            Request::Auth { mac, name } => match self.auth.verify(&nonce, &mac) {
+                Some(secret) => Ok((secret, name)),
+                None => bail!("wrong secret"),
+            },
+            _ => bail!("not authenticated"),
         }
-        Ok(())
+    }
+}
+
+/// Reads a request of at most `max_len` bytes, so that a viewer can't make the host allocate
+/// more.
+async fn read_request<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    max_len: usize,
+) -> anyhow::Result<Request> {
+    let len = reader.read_u32().await? as usize;
+    ensure!(len <= max_len, "request too long: {} bytes", len);
+    let mut payload = vec![0; len];
+    reader.read_exact(&mut payload).await?;
+    Request::decode(&payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::auth::MAC_LEN;
+
+    /// Returns a request as the viewer sends it, with its length.
+    fn request(payload: &[u8]) -> Vec<u8> {
+        let mut bytes = (payload.len() as u32).to_be_bytes().to_vec();
+        bytes.extend_from_slice(payload);
+        bytes
+    }
+
+    #[tokio::test]
+    async fn requests_longer_than_the_limit_are_refused() {
+        let input = request(&[1; 101]);
+        assert!(read_request(&mut &input[..], 100).await.is_err());
+        assert!(matches!(read_request(&mut &input[..], 101).await, Ok(Request::Input(_))));
+    }
+
+    #[tokio::test]
+    async fn authentication_fits_in_the_limit() {
+        let mut auth = vec![0; 1 + MAC_LEN];
+        auth.extend_from_slice(&[b'n'; 256]);
+        let auth = request(&auth);
+        assert!(read_request(&mut &auth[..], MAX_AUTH_REQUEST_LEN).await.is_ok());
+        // a request sent in place of the authentication, too long to be one.
+        let input = request(&[1; MAX_AUTH_REQUEST_LEN + 1]);
+        assert!(read_request(&mut &input[..], MAX_AUTH_REQUEST_LEN).await.is_err());
     }
 }
@@ -1,43 +1,420 @@
+pub mod access;
+pub mod auth;
 mod client;
+pub mod protocol;
+pub mod registry;
+pub mod tls;
 
+use std::collections::HashMap;
+use std::fmt;
 use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::Arc;
 
-use tokio::sync::broadcast;
+use tokio::sync::{broadcast, mpsc, oneshot};
 use tokio::net::TcpListener;
-use log::{error, info};
+use tokio::io::AsyncWriteExt;
+use tokio::task::JoinHandle;
+use tokio_rustls::TlsAcceptor;
+use log::{error, info, warn};
 
-use crate::cell::Cell;
+use crate::layout::Rect;
+use access::{Access, Ban, Connections};
+use auth::Auth;
 use client::Client;
+use protocol::Message;
+use registry::Stats;
 
-pub struct Network {
-    sender: broadcast::Sender<Vec<(usize, usize, Cell)>>,
+/// Changes in the set of connected clients, reported to the host. Clients are identified by a
+/// number, unique for the cast.
+pub enum NetworkEvent {
+    /// A viewer connected and authenticated, with the name it chose.
+    Connected {
+        id: usize,
+        addr: SocketAddr,
+        name: String,
+        stats: Arc<Stats>,
+    },
+    Disconnected { id: usize },
+    /// A connection was refused.
+    Rejected { addr: SocketAddr, reason: String },
+    /// Keys typed by a viewer.
+    Input { id: usize, input: Vec<u8> },
+    ControlRequested { id: usize },
+    /// A viewer asks for `count` rows from the absolute line `start`.
+    LinesRequested { id: usize, start: usize, count: usize },
+    /// A viewer missed frames, and needs a keyframe to catch up.
+    Lagged,
+}
+
+/// What the host asks the network to do.
+pub enum NetworkCommand {
+    Kick(usize),
+    /// Refuse an address or a name for the rest of the cast, kicking the viewers it matches.
+    Ban(Ban),
+    /// Send a message to one viewer only.
+    Send { id: usize, message: Message },
+}
+
+/// What a viewer is allowed to do, from least to most.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Permission {
+    /// Only watch.
+    ReadOnly,
+    /// Watch, and ask the host for the floor.
+    RequestControl,
+    /// Take the floor when no other viewer has it, without asking the host if it passed
+    /// `--auto-grant`. Otherwise, ask like `RequestControl`.
+    Full,
+}
+
+impl FromStr for Permission {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read-only" => Ok(Permission::ReadOnly),
+            "request-control" => Ok(Permission::RequestControl),
+            "full" => Ok(Permission::Full),
+            _ => Err(format!("invalid permission: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Permission {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Permission::ReadOnly => write!(f, "read-only"),
+            Permission::RequestControl => write!(f, "request-control"),
+            Permission::Full => write!(f, "full"),
+        }
+    }
+}
+
+/// What the client tasks report to the network.
+pub enum Update {
+    /// The viewer answered the challenge with the `secret`th secret. It waits to be admitted, or
+    /// told why not, before it gets any frame.
+    Authenticated {
+        id: usize,
+        secret: usize,
+        name: String,
+        admitted: oneshot::Sender<Result<(), String>>,
+    },
+    Rejected { id: usize, reason: String },
+    Input { id: usize, input: Vec<u8> },
+    AskControl { id: usize },
+    GetLines { id: usize, start: usize, count: usize },
+    Lagged { id: usize },
+    Closed { id: usize },
+}
+
+struct ClientHandle {
+    task: JoinHandle<()>,
     addr: SocketAddr,
+    /// messages for this client only.
+    direct: mpsc::UnboundedSender<Arc<[u8]>>,
+    /// the name of the viewer, once it is authenticated and the host was told about it.
+    name: Option<String>,
+    stats: Arc<Stats>,
+}
+
+pub struct Network {
+    /// Encoded messages, shared by all the clients.
+    sender: broadcast::Sender<Arc<[u8]>>,
+    /// bound by the host, so that it fails to start if the address is taken.
+    listener: TcpListener,
+    rect: Rect,
+    events: mpsc::UnboundedSender<NetworkEvent>,
+    commands: mpsc::UnboundedReceiver<NetworkCommand>,
+    access: Access,
+    /// encrypts the connections, if set.
+    tls: Option<TlsAcceptor>,
 }
 
 impl Network {
     pub fn new(
-        sender: broadcast::Sender<Vec<(usize, usize, Cell)>>,
-        addr: SocketAddr,
+        sender: broadcast::Sender<Arc<[u8]>>,
+        listener: TcpListener,
+        rect: Rect,
+        events: mpsc::UnboundedSender<NetworkEvent>,
+        commands: mpsc::UnboundedReceiver<NetworkCommand>,
+        access: Access,
+        tls: Option<TlsAcceptor>,
     ) -> Self {
         Self {
             sender,
-            addr,
+            listener,
+            rect,
+            events,
+            commands,
+            access,
+            tls,
         }
     }
 
-    pub async fn run(self) -> anyhow::Result<()> {
-        let listener = TcpListener::bind(self.addr).await.unwrap();
+    pub async fn run(mut self) -> anyhow::Result<()> {
+        let hello: Arc<[u8]> = Message::Hello {
+            width: self.rect.width,
+            height: self.rect.height,
+        }
+        .encode()
+        .into();
+        let mut clients: HashMap<usize, ClientHandle> = HashMap::new();
+        let mut next_id = 1;
+        let (updates_sender, mut updates) = mpsc::unbounded_channel();
         loop {
-            match listener.accept().await {
-                Ok((stream, _addr)) => {
-                    let client = Client::new(stream, self.sender.subscribe());
-                    info!("new client connected");
-                    tokio::task::spawn(client.run());
-                }
-                Err(e) => {
-                    error!("{}", e);
-                }
+            tokio::select! {
+                result = self.listener.accept() => match result {
+                    Ok((mut stream, addr)) => {
+                        let connections = connections(&clients, addr);
+                        if let Err(reason) = self.access.check(addr.ip(), connections) {
+                            let reason = reason.to_string();
+                            warn!("connection from {} refused: {}", addr, reason);
+                            let denied = Message::Denied(reason.clone()).encode();
+                            tokio::task::spawn(async move {
+                                let _ = stream.write_all(&denied).await;
+                            });
+                            let _ = self.events.send(NetworkEvent::Rejected { addr, reason });
+                            continue;
+                        }
+                        let id = next_id;
+                        next_id += 1;
+                        info!("client {} connected from {}", id, addr);
+                        let (direct, direct_receiver) = mpsc::unbounded_channel();
+                        let stats = Arc::new(Stats::default());
+                        let client = Client::new(
+                            id,
+                            stream,
+                            self.sender.clone(),
+                            hello.clone(),
+                            self.access.auth.clone(),
+                            direct_receiver,
+                            stats.clone(),
+                        );
+                        let tls = self.tls.clone();
+                        let updates = updates_sender.clone();
+                        let task = tokio::task::spawn(async move {
+                            let result = match tls {
+                                Some(tls) => match client.encrypt(&tls).await {
+                                    Ok(client) => client.run(updates.clone()).await,
+                                    Err(e) => Err(e),
+                                },
+                                None => client.run(updates.clone()).await,
+                            };
+                            if let Err(e) = result {
+                                error!("client {} error: {}", id, e);
+                            }
+                            let _ = updates.send(Update::Closed { id });
+                        });
+                        let handle = ClientHandle {
+                            task,
+                            addr,
+                            direct,
+                            name: None,
+                            stats,
+                        };
+                        clients.insert(id, handle);
+                    }
+                    Err(e) => {
+                        error!("{}", e);
+                    }
+                },
+                Some(update) = updates.recv() => match update {
+                    Update::Authenticated { id, secret, name, admitted } => {
+                        let name = registry::sanitize_name(&name);
+                        let viewers = clients.values().filter(|client| client.name.is_some());
+                        let viewers = viewers.count();
+                        let addr = match clients.get(&id) {
+                            Some(client) => client.addr,
+                            None => continue,
+                        };
+                        // names are only known now, so a banned one can't be refused earlier.
+                        if let Err(reason) = self.access.admit(addr.ip(), &name, viewers) {
+                            warn!("client {} from {} refused: {}", id, addr, reason);
+                            // the client tells the viewer, and closes the connection.
+                            clients.remove(&id);
+                            let _ = admitted.send(Err(reason.clone()));
+                            let _ = self.events.send(NetworkEvent::Rejected { addr, reason });
+                        } else if let Some(client) = clients.get_mut(&id) {
+                            match *self.access.auth {
+                                Auth::Passphrase(_) => {
+                                    info!("client {} authenticated with the passphrase", id)
+                                }
+                                Auth::Tokens(_) => {
+                                    info!("client {} authenticated with token {}", id, secret)
+                                }
+                            }
+                            client.name = Some(name.clone());
+                            let _ = admitted.send(Ok(()));
+                            let _ = self.events.send(NetworkEvent::Connected {
+                                id,
+                                addr: client.addr,
+                                name,
+                                stats: client.stats.clone(),
+                            });
+                        }
+                    }
+                    Update::Rejected { id, reason } => {
+                        if let Some(client) = clients.get(&id) {
+                            warn!("client {} from {} rejected: {}", id, client.addr, reason);
+                            self.access.fail(client.addr.ip());
+                            let addr = client.addr;
+                            let _ = self.events.send(NetworkEvent::Rejected { addr, reason });
+                        }
+                    }
+                    Update::Input { id, input } => {
+                        let _ = self.events.send(NetworkEvent::Input { id, input });
+                    }
+                    Update::AskControl { id } => {
+                        let _ = self.events.send(NetworkEvent::ControlRequested { id });
+                    }
+                    Update::GetLines { id, start, count } => {
+                        let _ = self.events.send(NetworkEvent::LinesRequested { id, start, count });
+                    }
+                    Update::Lagged { id } => {
+                        warn!("client {} lagged behind", id);
+                        let _ = self.events.send(NetworkEvent::Lagged);
+                    }
+                    Update::Closed { id } => {
+                        if let Some(client) = clients.remove(&id) {
+                            info!("client {} disconnected", id);
+                            if client.name.is_some() {
+                                let _ = self.events.send(NetworkEvent::Disconnected { id });
+                            }
+                        }
+                    }
+                },
+                Some(command) = self.commands.recv() => match command {
+                    NetworkCommand::Kick(id) => {
+                        if let Some(client) = clients.remove(&id) {
+                            info!("client {} kicked", id);
+                            client.task.abort();
+                            if client.name.is_some() {
+                                let _ = self.events.send(NetworkEvent::Disconnected { id });
+                            }
+                        }
+                    }
+                    NetworkCommand::Ban(ban) => {
+                        info!("banned {:?}", ban);
+                        self.access.ban(ban);
+                        let banned: Vec<_> = clients
+                            .iter()
+                            .filter(|(_, client)| {
+                                self.access.is_banned(client.addr.ip(), client.name.as_deref())
+                            })
+                            .map(|(&id, _)| id)
+                            .collect();
+                        for id in banned {
+                            if let Some(client) = clients.remove(&id) {
+                                info!("client {} kicked", id);
+                                client.task.abort();
+                                if client.name.is_some() {
+                                    let _ = self.events.send(NetworkEvent::Disconnected { id });
+                                }
+                            }
+                        }
+                    }
+                    NetworkCommand::Send { id, message } => {
+                        if let Some(client) = clients.get(&id) {
+                            let _ = client.direct.send(message.encode().into());
+                        }
+                    }
+                },
+            }
+        }
+    }
+}
+
+/// Returns the connections open when a new one comes from `addr`.
+fn connections(clients: &HashMap<usize, ClientHandle>, addr: SocketAddr) -> Connections {
+    let mut connections = Connections::default();
+    for client in clients.values() {
+        if client.name.is_some() {
+            connections.viewers += 1;
+        } else {
+            connections.pending += 1;
+            if client.addr.ip() == addr.ip() {
+                connections.pending_from_addr += 1;
             }
         }
     }
+    connections
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hmac::{Hmac, Mac, NewMac};
+    use sha2::Sha256;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpStream;
+
+    const TOKEN: &str = "token";
+
+    /// Returns the next message, with its length.
+    async fn read_message(stream: &mut TcpStream) -> Vec<u8> {
+        let len = stream.read_u32().await.unwrap();
+        let mut message = len.to_be_bytes().to_vec();
+        message.resize(4 + len as usize, 0);
+        stream.read_exact(&mut message[4..]).await.unwrap();
+        message
+    }
+
+    /// Connects as `name`, and returns the stream and the message that follows the
+    /// authentication.
+    async fn join(addr: SocketAddr, name: &str) -> (TcpStream, Vec<u8>) {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let challenge = read_message(&mut stream).await;
+        let mut mac = Hmac::<Sha256>::new_from_slice(TOKEN.as_bytes()).unwrap();
+        mac.update(&challenge[5..]);
+        let mut auth = vec![0];
+        auth.extend_from_slice(&mac.finalize().into_bytes());
+        auth.extend_from_slice(name.as_bytes());
+        stream.write_u32(auth.len() as u32).await.unwrap();
+        stream.write_all(&auth).await.unwrap();
+        let message = read_message(&mut stream).await;
+        (stream, message)
+    }
+
+    #[tokio::test]
+    async fn banned_name_denied_before_any_frame() {
+        let (sender, _) = broadcast::channel(16);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (events_sender, mut events) = mpsc::unbounded_channel();
+        let (commands, commands_receiver) = mpsc::unbounded_channel();
+        let access = Access::new(Auth::Tokens(vec![TOKEN.to_string()]), vec![], vec![], Some(1));
+        let rect = Rect::new(0, 0, 80, 24);
+        let network = Network::new(
+            sender.clone(),
+            listener,
+            rect,
+            events_sender,
+            commands_receiver,
+            access,
+            None,
+        );
+        tokio::task::spawn(network.run());
+        commands.send(NetworkCommand::Ban(Ban::Name("mallory".to_string()))).ok().unwrap();
+
+        let denied = Message::Denied("mallory is banned".to_string()).encode();
+        assert_eq!(join(addr, "mallory").await.1, denied);
+        assert!(matches!(events.recv().await, Some(NetworkEvent::Rejected { .. })));
+
+        let hello = Message::Hello { width: 80, height: 24 }.encode();
+        let (mut alice, message) = join(addr, "alice").await;
+        assert_eq!(message, hello);
+        assert!(matches!(events.recv().await, Some(NetworkEvent::Connected { .. })));
+        // what the host broadcasts once told about alice reaches her.
+        let floor = Message::Floor(None).encode();
+        sender.send(floor.clone().into()).ok().unwrap();
+        assert_eq!(read_message(&mut alice).await, floor);
+
+        // alice is in, so the maximum is reached before the challenge.
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let denied = Message::Denied("too many viewers".to_string()).encode();
+        assert_eq!(read_message(&mut stream).await, denied);
+        drop(alice);
+    }
 }
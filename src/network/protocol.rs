@@ -0,0 +1,372 @@
+//! Wire format of the messages exchanged with the viewers.
+//!
+//! Every message is a big endian `u32` length, followed by a tag byte and the payload.
+
+use anyhow::{bail, ensure};
+
+use super::auth::{MAC_LEN, NONCE_LEN};
+use super::Permission;
+use crate::cell::Cell;
+use crate::frame::Frame;
+use crate::palette::{PaletteIndex, Rgb};
+use crate::style::Color;
+
+const HELLO: u8 = 0;
+const FRAME: u8 = 1;
+const SECRET_INPUT: u8 = 2;
+const CHALLENGE: u8 = 3;
+const DENIED: u8 = 4;
+const PERMISSION: u8 = 5;
+const FLOOR: u8 = 6;
+const VIEWER_ID: u8 = 7;
+const PRESENCE: u8 = 8;
+const LINES: u8 = 9;
+
+const AUTH: u8 = 0;
+const INPUT: u8 = 1;
+const ASK_CONTROL: u8 = 2;
+const GET_LINES: u8 = 3;
+
+/// Largest request before the viewer is authenticated, where only `Request::Auth` is expected,
+/// with a name of a few words.
+pub const MAX_AUTH_REQUEST_LEN: usize = 1 + MAC_LEN + 256;
+/// Largest request an authenticated viewer can send.
+pub const MAX_REQUEST_LEN: usize = 64 * 1024;
+
+/// Messages sent to the viewers.
+pub enum Message {
+    /// First message sent to a viewer, with a nonce to authenticate with.
+    Challenge([u8; NONCE_LEN]),
+    /// The viewer could not authenticate. The connection is closed after it.
+    Denied(String),
+    /// Sent once the viewer is authenticated, with the size of the cast.
+    Hello { width: usize, height: usize },
+    Frame(Frame),
+    /// Whether the program is reading input that is not echoed, like a password.
+    SecretInput(bool),
+    /// What the viewer is allowed to do, sent to that viewer only.
+    Permission(Permission),
+    /// The viewer whose keys go to the pty, or none if it is the host.
+    Floor(Option<usize>),
+    /// The id of the viewer in the cast, sent to that viewer only.
+    ViewerId(usize),
+    /// The id, name and permission of every viewer, sent when one joins or leaves.
+    Presence(Vec<(usize, String, Permission)>),
+    /// Rows from the absolute line `start`, in answer to `Request::GetLines`.
+    Lines { start: usize, rows: Vec<Vec<Cell>> },
+}
+
+impl Message {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut encoder = Encoder(vec![0; 4]);
+        match self {
+            Message::Challenge(nonce) => {
+                encoder.u8(CHALLENGE);
+                encoder.0.extend_from_slice(nonce);
+            }
+            Message::Denied(reason) => {
+                encoder.u8(DENIED);
+                encoder.str(reason);
+            }
+            Message::Hello { width, height } => {
+                encoder.u8(HELLO);
+                encoder.u16(*width as u16);
+                encoder.u16(*height as u16);
+            }
+            Message::Frame(frame) => {
+                encoder.u8(FRAME);
+                encoder.frame(frame);
+            }
+            Message::SecretInput(secret) => {
+                encoder.u8(SECRET_INPUT);
+                encoder.u8(*secret as u8);
+            }
+            Message::Permission(permission) => {
+                encoder.u8(PERMISSION);
+                encoder.u8(*permission as u8);
+            }
+            Message::Floor(holder) => {
+                encoder.u8(FLOOR);
+                encoder.option(*holder, |e, id| e.u32(id as u32));
+            }
+            Message::ViewerId(id) => {
+                encoder.u8(VIEWER_ID);
+                encoder.u32(*id as u32);
+            }
+            Message::Presence(viewers) => {
+                encoder.u8(PRESENCE);
+                encoder.u16(viewers.len() as u16);
+                for (id, name, permission) in viewers {
+                    encoder.u32(*id as u32);
+                    encoder.str(name);
+                    encoder.u8(*permission as u8);
+                }
+            }
+            Message::Lines { start, rows } => {
+                encoder.u8(LINES);
+                encoder.u64(*start as u64);
+                encoder.u16(rows.len() as u16);
+                for row in rows {
+                    encoder.u16(row.len() as u16);
+                    row.iter().for_each(|cell| encoder.cell_content(cell));
+                }
+            }
+        }
+        let len = (encoder.0.len() - 4) as u32;
+        encoder.0[..4].copy_from_slice(&len.to_be_bytes());
+        encoder.0
+    }
+}
+
+/// Messages sent by the viewers.
+pub enum Request {
+    /// The HMAC-SHA256 of the challenge's nonce, keyed with the viewer's secret, and the name the
+    /// viewer is shown with.
+    Auth { mac: [u8; MAC_LEN], name: String },
+    /// Keys typed by the viewer, only sent to the pty if the viewer has the floor.
+    Input(Vec<u8>),
+    /// The viewer asks for the floor.
+    AskControl,
+    /// The viewer asks for `count` rows from the absolute line `start`, e.g. to read the output
+    /// of a command that scrolled off the screen.
+    GetLines { start: usize, count: usize },
+}
+
+impl Request {
+    /// Decodes a request, without its length.
+    pub fn decode(payload: &[u8]) -> anyhow::Result<Self> {
+        match payload {
+            [AUTH, rest @ ..] => {
+                ensure!(rest.len() >= MAC_LEN, "invalid authentication");
+                let (mac_bytes, name) = rest.split_at(MAC_LEN);
+                let mut mac = [0; MAC_LEN];
+                mac.copy_from_slice(mac_bytes);
+                let name = String::from_utf8_lossy(name).into_owned();
+                Ok(Request::Auth { mac, name })
+            }
+            [INPUT, input @ ..] => Ok(Request::Input(input.to_vec())),
+            [ASK_CONTROL] => Ok(Request::AskControl),
+            [GET_LINES, rest @ ..] => {
+                ensure!(rest.len() == 10, "invalid lines request");
+                let mut start = [0; 8];
+                start.copy_from_slice(&rest[..8]);
+                Ok(Request::GetLines {
+                    start: u64::from_be_bytes(start) as usize,
+                    count: u16::from_be_bytes([rest[8], rest[9]]) as usize,
+                })
+            }
+            [tag, ..] => bail!("unknown request: {}", tag),
+            [] => bail!("empty request"),
+        }
+    }
+}
+
+struct Encoder(Vec<u8>);
+
+impl Encoder {
+    fn u8(&mut self, v: u8) {
+        self.0.push(v);
+    }
+
+    fn u16(&mut self, v: u16) {
+        self.0.extend_from_slice(&v.to_be_bytes());
+    }
+
+    fn u32(&mut self, v: u32) {
+        self.0.extend_from_slice(&v.to_be_bytes());
+    }
+
+    fn u64(&mut self, v: u64) {
+        self.0.extend_from_slice(&v.to_be_bytes());
+    }
+
+    fn str(&mut self, s: &str) {
+        self.u32(s.len() as u32);
+        self.0.extend_from_slice(s.as_bytes());
+    }
+
+    fn option<T>(&mut self, v: Option<T>, mut f: impl FnMut(&mut Self, T)) {
+        match v {
+            Some(v) => {
+                self.u8(1);
+                f(self, v);
+            }
+            None => self.u8(0),
+        }
+    }
+
+    fn color(&mut self, color: Color) {
+        match color {
+            Color::Rgb(r, g, b) => {
+                self.u8(COLOR_RGB);
+                self.rgb(Rgb(r, g, b));
+            }
+            Color::Indexed(i) => {
+                self.u8(COLOR_INDEXED);
+                self.u8(i);
+            }
+            named => self.u8(NAMED_COLORS.iter().position(|&c| c == named).unwrap_or(0) as u8),
+        }
+    }
+
+    fn rgb(&mut self, Rgb(r, g, b): Rgb) {
+        self.0.extend_from_slice(&[r, g, b]);
+    }
+
+    fn cell(&mut self, (x, y, cell): (usize, usize, Cell)) {
+        self.u16(x as u16);
+        self.u16(y as u16);
+        self.cell_content(&cell);
+    }
+
+    fn cell_content(&mut self, cell: &Cell) {
+        self.u32(cell.symbol as u32);
+        self.color(cell.style.fg);
+        self.color(cell.style.bg);
+        self.u16(cell.style.modifier.bits());
+    }
+
+    fn frame(&mut self, frame: &Frame) {
+        self.u16(frame.scrolls.len() as u16);
+        for scroll in &frame.scrolls {
+            self.u16(scroll.top as u16);
+            self.u16(scroll.bottom as u16);
+            self.u16(scroll.n as i16 as u16);
+        }
+        self.u32(frame.cells.len() as u32);
+        frame.cells.iter().for_each(|&cell| self.cell(cell));
+        self.option(frame.clipboard.as_ref(), |e, clipboard| {
+            e.str(&clipboard.selection);
+            e.str(&clipboard.content);
+        });
+        self.u16(frame.palette.len() as u16);
+        for change in &frame.palette {
+            match change.index {
+                PaletteIndex::Indexed(i) => {
+                    self.u8(0);
+                    self.u8(i);
+                }
+                PaletteIndex::Foreground => self.u8(1),
+                PaletteIndex::Background => self.u8(2),
+                PaletteIndex::Cursor => self.u8(3),
+            }
+            self.option(change.color, Encoder::rgb);
+        }
+        self.u16(frame.commands.len() as u16);
+        for command in &frame.commands {
+            self.u64(command.prompt_line as u64);
+            self.option(command.output_line, |e, line| e.u64(line as u64));
+            self.u64(command.end_line as u64);
+            self.str(&command.command);
+            self.option(command.cwd.as_deref(), Encoder::str);
+            self.option(command.exit_code, |e, code| e.u32(code as u32));
+        }
+    }
+}
+
+const COLOR_RGB: u8 = 0xfe;
+const COLOR_INDEXED: u8 = 0xff;
+
+/// Colors encoded by their index in this list.
+const NAMED_COLORS: [Color; 17] = [
+    Color::Reset,
+    Color::Black,
+    Color::Red,
+    Color::Green,
+    Color::Yellow,
+    Color::Blue,
+    Color::Magenta,
+    Color::Cyan,
+    Color::Gray,
+    Color::DarkGray,
+    Color::LightRed,
+    Color::LightGreen,
+    Color::LightYellow,
+    Color::LightBlue,
+    Color::LightMagenta,
+    Color::LightCyan,
+    Color::White,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::Scroll;
+
+    /// Returns the message without its length, checking it.
+    fn encode(message: Message) -> Vec<u8> {
+        let bytes = message.encode();
+        let len = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+        assert_eq!(len, bytes.len() - 4);
+        bytes[4..].to_vec()
+    }
+
+    #[test]
+    fn encode_hello_and_floor() {
+        assert_eq!(encode(Message::Hello { width: 80, height: 24 }), [HELLO, 0, 80, 0, 24]);
+        assert_eq!(encode(Message::Floor(None)), [FLOOR, 0]);
+        assert_eq!(encode(Message::Floor(Some(3))), [FLOOR, 1, 0, 0, 0, 3]);
+        assert_eq!(encode(Message::Denied("no".to_string())), [DENIED, 0, 0, 0, 2, b'n', b'o']);
+    }
+
+    #[test]
+    fn encode_presence() {
+        let presence = Message::Presence(vec![(2, "bo".to_string(), Permission::Full)]);
+        assert_eq!(encode(presence), [PRESENCE, 0, 1, 0, 0, 0, 2, 0, 0, 0, 2, b'b', b'o', 2]);
+    }
+
+    #[test]
+    fn encode_frame() {
+        let mut cell = Cell::default();
+        cell.set_symbol('a');
+        let frame = Frame {
+            scrolls: vec![Scroll { top: 1, bottom: 3, n: -1 }],
+            cells: vec![(2, 1, cell)],
+            ..Frame::default()
+        };
+        let mut expected = vec![FRAME, 0, 1, 0, 1, 0, 3, 0xff, 0xff, 0, 0, 0, 1, 0, 2, 0, 1];
+        // the symbol, the reset colors and no modifier.
+        expected.extend_from_slice(&[0, 0, 0, b'a', 0, 0, 0, 0]);
+        // no clipboard, palette or commands.
+        expected.extend_from_slice(&[0, 0, 0, 0, 0]);
+        assert_eq!(encode(Message::Frame(frame)), expected);
+    }
+
+    #[test]
+    fn decode_auth() {
+        let mut payload = vec![AUTH];
+        payload.extend_from_slice(&[7; MAC_LEN]);
+        payload.extend_from_slice("zoé".as_bytes());
+        match Request::decode(&payload).unwrap() {
+            Request::Auth { mac, name } => {
+                assert_eq!((mac, name.as_str()), ([7; MAC_LEN], "zoé"));
+            }
+            _ => panic!("not an authentication"),
+        }
+        assert!(Request::decode(&payload[..MAC_LEN]).is_err());
+    }
+
+    #[test]
+    fn decode_other_requests() {
+        match Request::decode(&[INPUT, b'l', b's']).unwrap() {
+            Request::Input(input) => assert_eq!(input, b"ls"),
+            _ => panic!("not input"),
+        }
+        assert!(matches!(Request::decode(&[ASK_CONTROL]), Ok(Request::AskControl)));
+        assert!(Request::decode(&[ASK_CONTROL, 0]).is_err());
+        assert!(Request::decode(&[42]).is_err());
+        assert!(Request::decode(&[]).is_err());
+    }
+
+    #[test]
+    fn decode_get_lines() {
+        let mut payload = vec![GET_LINES];
+        payload.extend_from_slice(&42u64.to_be_bytes());
+        payload.extend_from_slice(&100u16.to_be_bytes());
+        match Request::decode(&payload).unwrap() {
+            Request::GetLines { start, count } => assert_eq!((start, count), (42, 100)),
+            _ => panic!("not a lines request"),
+        }
+        assert!(Request::decode(&payload[..5]).is_err());
+    }
+}
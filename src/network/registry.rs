@@ -0,0 +1,201 @@
+//! The viewers connected to the cast.
+
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use super::protocol::Message;
+use super::Permission;
+
+/// Longest display name a viewer can choose.
+const MAX_NAME_LEN: usize = 32;
+
+/// Counters updated by the client task while it sends to the viewer.
+#[derive(Default)]
+pub struct Stats {
+    bytes_sent: AtomicU64,
+    /// messages the viewer was too slow to get.
+    lagged: AtomicU64,
+}
+
+impl Stats {
+    pub fn sent(&self, bytes: usize) {
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn lag(&self, messages: u64) {
+        self.lagged.fetch_add(messages, Ordering::Relaxed);
+    }
+
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent.load(Ordering::Relaxed)
+    }
+
+    pub fn lagged(&self) -> u64 {
+        self.lagged.load(Ordering::Relaxed)
+    }
+}
+
+pub struct Viewer {
+    pub name: String,
+    pub addr: SocketAddr,
+    pub connected: Instant,
+    pub permission: Permission,
+    pub stats: Arc<Stats>,
+}
+
+/// The authenticated viewers, by id.
+#[derive(Default)]
+pub struct Registry {
+    viewers: BTreeMap<usize, Viewer>,
+}
+
+impl Registry {
+    pub fn insert(&mut self, id: usize, viewer: Viewer) {
+        self.viewers.insert(id, viewer);
+    }
+
+    pub fn remove(&mut self, id: usize) -> Option<Viewer> {
+        self.viewers.remove(&id)
+    }
+
+    pub fn get(&self, id: usize) -> Option<&Viewer> {
+        self.viewers.get(&id)
+    }
+
+    /// Changes what the viewer is allowed to do. Returns whether there is such a viewer.
+    pub fn set_permission(&mut self, id: usize, permission: Permission) -> bool {
+        match self.viewers.get_mut(&id) {
+            Some(viewer) => {
+                viewer.permission = permission;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn contains(&self, id: usize) -> bool {
+        self.viewers.contains_key(&id)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.viewers.is_empty()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.viewers.values().map(|viewer| viewer.name.clone()).collect()
+    }
+
+    /// Returns how the viewer is shown to the host, with its name and id.
+    pub fn label(&self, id: usize) -> String {
+        match self.viewers.get(&id) {
+            Some(viewer) => format!("{} ({})", viewer.name, id),
+            None => format!("viewer {}", id),
+        }
+    }
+
+    /// Returns a line for each viewer, for the host.
+    pub fn describe(&self) -> Vec<String> {
+        self.viewers
+            .iter()
+            .map(|(id, viewer)| {
+                let minutes = viewer.connected.elapsed().as_secs() / 60;
+                format!(
+                    "{} {} ({}, {}m, {}, lag {}, {})",
+                    id,
+                    viewer.name,
+                    viewer.addr,
+                    minutes,
+                    format_bytes(viewer.stats.bytes_sent()),
+                    viewer.stats.lagged(),
+                    viewer.permission,
+                )
+            })
+            .collect()
+    }
+
+    /// Returns the message telling the viewers who is watching.
+    pub fn presence(&self) -> Message {
+        Message::Presence(
+            self.viewers
+                .iter()
+                .map(|(&id, viewer)| (id, viewer.name.clone(), viewer.permission))
+                .collect(),
+        )
+    }
+}
+
+/// Keeps the printable part of the name a viewer chose, `viewer` if there is none.
+pub fn sanitize_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_NAME_LEN)
+        .collect();
+    match name.trim() {
+        "" => "viewer".to_string(),
+        name => name.to_string(),
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    match bytes {
+        0..=1023 => format!("{}B", bytes),
+        1024..=1_048_575 => format!("{:.1}KiB", bytes as f64 / 1024.0),
+        _ => format!("{:.1}MiB", bytes as f64 / 1_048_576.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn viewer(name: &str) -> Viewer {
+        Viewer {
+            name: name.to_string(),
+            addr: "10.0.0.1:4000".parse().unwrap(),
+            connected: Instant::now(),
+            permission: Permission::ReadOnly,
+            stats: Arc::default(),
+        }
+    }
+
+    #[test]
+    fn sanitize() {
+        assert_eq!(sanitize_name("  alice\x1b[2J "), "alice[2J");
+        assert_eq!(sanitize_name("\x07\n"), "viewer");
+        assert_eq!(sanitize_name(&"é".repeat(40)), "é".repeat(MAX_NAME_LEN));
+    }
+
+    #[test]
+    fn viewers_by_id() {
+        let mut registry = Registry::default();
+        registry.insert(3, viewer("bob"));
+        registry.insert(1, viewer("alice"));
+        assert_eq!(registry.names(), ["alice", "bob"]);
+        assert_eq!(registry.label(3), "bob (3)");
+        assert_eq!(registry.label(2), "viewer 2");
+
+        registry.get(3).unwrap().stats.sent(2048);
+        assert_eq!(registry.describe()[1], "3 bob (10.0.0.1:4000, 0m, 2.0KiB, lag 0, read-only)");
+
+        assert!(registry.remove(1).is_some());
+        assert!(registry.set_permission(3, Permission::Full));
+        assert!(!registry.set_permission(1, Permission::Full));
+        match registry.presence() {
+            Message::Presence(viewers) => {
+                assert_eq!(viewers, [(3, "bob".to_string(), Permission::Full)]);
+            }
+            _ => panic!("not a presence message"),
+        }
+    }
+
+    #[test]
+    fn bytes() {
+        assert_eq!(format_bytes(1023), "1023B");
+        assert_eq!(format_bytes(1536), "1.5KiB");
+        assert_eq!(format_bytes(3 * 1_048_576), "3.0MiB");
+    }
+}
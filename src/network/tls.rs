@@ -0,0 +1,152 @@
+//! Encryption of the connections to the viewers.
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Context, Result};
+use sha2::{Digest, Sha256};
+use tokio_rustls::rustls::internal::pemfile;
+use tokio_rustls::rustls::{
+    Certificate, ClientConfig, NoClientAuth, PrivateKey, RootCertStore, ServerCertVerified,
+    ServerCertVerifier, ServerConfig, TLSError,
+};
+use tokio_rustls::webpki::DNSNameRef;
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+/// The certificate of the host, and what it takes to accept TLS connections with it.
+pub struct Tls {
+    pub acceptor: TlsAcceptor,
+    /// SHA-256 of the certificate, in hex, for the viewers to pin.
+    pub fingerprint: String,
+}
+
+impl Tls {
+    /// Generates a self-signed certificate, that lives as long as the cast.
+    pub fn self_signed() -> Result<Self> {
+        let generated = rcgen::generate_simple_self_signed(vec!["localhost".to_string()])?;
+        let cert = Certificate(generated.serialize_der()?);
+        let key = PrivateKey(generated.serialize_private_key_der());
+        Self::new(vec![cert], key)
+    }
+
+    /// Reads the certificate chain and the private key from PEM files.
+    pub fn from_files(cert: &Path, key: &Path) -> Result<Self> {
+        let certs = pemfile::certs(&mut open(cert)?)
+            .map_err(|_| anyhow!("invalid certificate in {}", cert.display()))?;
+        if certs.is_empty() {
+            bail!("no certificate in {}", cert.display());
+        }
+        let keys = match pemfile::pkcs8_private_keys(&mut open(key)?) {
+            Ok(keys) if !keys.is_empty() => keys,
+            _ => pemfile::rsa_private_keys(&mut open(key)?)
+                .map_err(|_| anyhow!("invalid private key in {}", key.display()))?,
+        };
+        match keys.into_iter().next() {
+            Some(key) => Self::new(certs, key),
+            None => bail!("no private key in {}", key.display()),
+        }
+    }
+
+    fn new(certs: Vec<Certificate>, key: PrivateKey) -> Result<Self> {
+        let fingerprint = fingerprint(&certs[0]);
+        let mut config = ServerConfig::new(NoClientAuth::new());
+        config.set_single_cert(certs, key)?;
+        Ok(Self {
+            acceptor: TlsAcceptor::from(Arc::new(config)),
+            fingerprint,
+        })
+    }
+}
+
+/// Returns a connector for viewers, that only accepts the certificate with `fingerprint`, the
+/// SHA-256 shown to the host, in hex, with or without colons. The certificate is usually
+/// self-signed, so it can't be checked against authorities.
+pub fn pinned_connector(fingerprint: &str) -> TlsConnector {
+    let fingerprint = fingerprint.replace(':', "").to_ascii_lowercase();
+    let mut config = ClientConfig::new();
+    config
+        .dangerous()
+        .set_certificate_verifier(Arc::new(Pinned(fingerprint)));
+    TlsConnector::from(Arc::new(config))
+}
+
+/// Accepts the certificate with the fingerprint shown to the host.
+struct Pinned(String);
+
+impl ServerCertVerifier for Pinned {
+    fn verify_server_cert(
+        &self,
+        _: &RootCertStore,
+        certs: &[Certificate],
+        _: DNSNameRef,
+        _: &[u8],
+    ) -> Result<ServerCertVerified, TLSError> {
+        match certs.first() {
+            Some(cert) if fingerprint(cert) == self.0 => Ok(ServerCertVerified::assertion()),
+            _ => Err(TLSError::General("fingerprint mismatch".to_string())),
+        }
+    }
+}
+
+fn fingerprint(cert: &Certificate) -> String {
+    Sha256::digest(&cert.0)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn open(path: &Path) -> Result<BufReader<File>> {
+    let file = File::open(path).with_context(|| format!("can't open {}", path.display()))?;
+    Ok(BufReader::new(file))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::*;
+
+    /// Returns what the host sent, over a connection pinned to `fingerprint`.
+    async fn connect(addr: SocketAddr, fingerprint: &str) -> std::io::Result<Vec<u8>> {
+        let connector = pinned_connector(fingerprint);
+        let stream = TcpStream::connect(addr).await?;
+        let name = DNSNameRef::try_from_ascii_str("localhost").unwrap();
+        let mut stream = connector.connect(name, stream).await?;
+        let mut received = Vec::new();
+        stream.read_to_end(&mut received).await?;
+        Ok(received)
+    }
+
+    #[tokio::test]
+    async fn pinned_fingerprint() {
+        let tls = Tls::self_signed().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let acceptor = tls.acceptor.clone();
+        tokio::task::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                if let Ok(mut stream) = acceptor.accept(stream).await {
+                    let _ = stream.write_all(b"hello").await;
+                    let _ = stream.shutdown().await;
+                }
+            }
+        });
+
+        assert_eq!(connect(addr, &tls.fingerprint).await.unwrap(), b"hello");
+        // as some tools show it.
+        let colons = tls.fingerprint.to_uppercase().as_bytes().chunks(2).fold(
+            String::new(),
+            |colons, byte| colons + ":" + std::str::from_utf8(byte).unwrap(),
+        );
+        assert_eq!(connect(addr, &colons[1..]).await.unwrap(), b"hello");
+        // the certificate of someone else, e.g. in the middle.
+        let other = Tls::self_signed().unwrap();
+        assert!(connect(addr, &other.fingerprint).await.is_err());
+    }
+}
@@ -0,0 +1,314 @@
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+impl Rgb {
+    /// Parses an X11 color spec as used by OSC 4/10/11/12: `rgb:r/g/b` with 1 to 4 hex digits
+    /// per channel, or `#rgb`, `#rrggbb`, `#rrrgggbbb` and `#rrrrggggbbbb`.
+    pub fn parse(spec: &[u8]) -> Option<Self> {
+        let spec = std::str::from_utf8(spec).ok()?;
+        if let Some(channels) = spec.strip_prefix("rgb:") {
+            let mut channels = channels.split('/').map(parse_scaled_channel);
+            let rgb = Rgb(channels.next()??, channels.next()??, channels.next()??);
+            return channels.next().is_none().then_some(rgb);
+        }
+        let digits = spec.strip_prefix('#')?;
+        if digits.is_empty() || digits.len() % 3 != 0 || digits.len() > 12 {
+            return None;
+        }
+        let len = digits.len() / 3;
+        // in the `#` form, the leftmost digits are the most significant ones.
+        let channel = |i: usize| {
+            let value = u16::from_str_radix(digits.get(i * len..(i + 1) * len)?, 16).ok()?;
+            Some((value << (16 - len * 4) >> 8) as u8)
+        };
+        Some(Rgb(channel(0)?, channel(1)?, channel(2)?))
+    }
+}
+
+/// Parses a channel of a `rgb:` spec, where `h`, `hh`, `hhh` and `hhhh` are all scaled to the full
+/// range.
+fn parse_scaled_channel(channel: &str) -> Option<u8> {
+    if channel.is_empty() || channel.len() > 4 {
+        return None;
+    }
+    let value = u32::from_str_radix(channel, 16).ok()?;
+    let max = (1 << (channel.len() * 4)) - 1;
+    Some((value * 255 / max) as u8)
+}
+
+impl fmt::Display for Rgb {
+    /// Formats the color the way xterm answers palette queries.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "rgb:{:02x}{:02x}/{:02x}{:02x}/{:02x}{:02x}",
+            self.0, self.0, self.1, self.1, self.2, self.2
+        )
+    }
+}
+
+/// A color slot that can be queried or changed with OSC sequences.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaletteIndex {
+    Indexed(u8),
+    Foreground,
+    Background,
+    Cursor,
+}
+
+impl PaletteIndex {
+    /// The dynamic colors, in the order used by OSC 10, 11 and 12.
+    pub const DYNAMIC: [PaletteIndex; 3] = [
+        PaletteIndex::Foreground,
+        PaletteIndex::Background,
+        PaletteIndex::Cursor,
+    ];
+
+    /// The OSC number used to set or query a dynamic color.
+    pub fn osc(&self) -> Option<u8> {
+        match self {
+            PaletteIndex::Indexed(_) => None,
+            PaletteIndex::Foreground => Some(10),
+            PaletteIndex::Background => Some(11),
+            PaletteIndex::Cursor => Some(12),
+        }
+    }
+}
+
+/// A change of a palette entry. A `None` color resets the entry to the terminal's default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PaletteChange {
+    pub index: PaletteIndex,
+    pub color: Option<Rgb>,
+}
+
+pub struct Palette {
+    colors: Colors,
+    /// the colors the cast started with, that the entries are reset to.
+    initial: Colors,
+    /// the entries the host terminal reported, that the viewers get the colors of rather than
+    /// their own defaults.
+    reported: Vec<PaletteIndex>,
+}
+
+impl Palette {
+    /// Returns the default palette with the colors the host terminal reported.
+    pub fn new(host_colors: &[(PaletteIndex, Rgb)]) -> Self {
+        let mut colors = Colors::default();
+        for &(index, color) in host_colors {
+            colors.set(index, color);
+        }
+        Self {
+            initial: colors.clone(),
+            colors,
+            reported: host_colors.iter().map(|&(index, _)| index).collect(),
+        }
+    }
+
+    pub fn get(&self, index: PaletteIndex) -> Rgb {
+        self.colors.get(index)
+    }
+
+    /// Sets the color of `index`, or resets it to its initial color if `color` is `None`.
+    pub fn set(&mut self, index: PaletteIndex, color: Option<Rgb>) {
+        let color = color.unwrap_or_else(|| self.initial.get(index));
+        self.colors.set(index, color);
+    }
+
+    /// Returns the color the viewers should give `index`, `None` for a default the host terminal
+    /// didn't report, the viewers having their own.
+    pub fn viewer_color(&self, index: PaletteIndex) -> Option<Rgb> {
+        let color = self.get(index);
+        let is_default = color == self.initial.get(index) && !self.reported.contains(&index);
+        Some(color).filter(|_| !is_default)
+    }
+
+    /// Returns the changes that turn any palette into this one, the host's colors included.
+    pub fn changes(&self) -> Vec<PaletteChange> {
+        (0..=255)
+            .map(PaletteIndex::Indexed)
+            .chain(PaletteIndex::DYNAMIC.iter().copied())
+            .map(|index| PaletteChange {
+                index,
+                color: self.viewer_color(index),
+            })
+            .collect()
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::new(&[])
+    }
+}
+
+/// Returns the queries asking the terminal for every palette entry.
+pub fn queries() -> String {
+    let mut queries = String::from("\x1b]4");
+    for i in 0..=255 {
+        queries.push_str(&format!(";{};?", i));
+    }
+    queries.push_str("\x07\x1b]10;?\x07\x1b]11;?\x07\x1b]12;?\x07");
+    queries
+}
+
+/// Parses the answers to the palette queries, `OSC 4 ; index ; spec` and `OSC 1x ; spec`,
+/// ignoring anything else.
+pub fn parse_replies(bytes: &[u8]) -> Vec<(PaletteIndex, Rgb)> {
+    bytes
+        .split(|&b| b == 0x1b)
+        .filter_map(|sequence| {
+            let sequence = sequence.strip_prefix(b"]")?;
+            let end = sequence.iter().position(|&b| b == 0x07).unwrap_or(sequence.len());
+            let params: Vec<&[u8]> = sequence[..end].split(|&b| b == b';').collect();
+            let index = match params[..] {
+                [b"4", index, _] => {
+                    PaletteIndex::Indexed(std::str::from_utf8(index).ok()?.parse().ok()?)
+                }
+                [b"10", _] => PaletteIndex::Foreground,
+                [b"11", _] => PaletteIndex::Background,
+                [b"12", _] => PaletteIndex::Cursor,
+                _ => return None,
+            };
+            Some((index, Rgb::parse(params.last()?)?))
+        })
+        .collect()
+}
+
+#[derive(Clone)]
+struct Colors {
+    indexed: [Rgb; 256],
+    foreground: Rgb,
+    background: Rgb,
+    cursor: Rgb,
+}
+
+impl Colors {
+    fn get(&self, index: PaletteIndex) -> Rgb {
+        match index {
+            PaletteIndex::Indexed(i) => self.indexed[i as usize],
+            PaletteIndex::Foreground => self.foreground,
+            PaletteIndex::Background => self.background,
+            PaletteIndex::Cursor => self.cursor,
+        }
+    }
+
+    fn set(&mut self, index: PaletteIndex, color: Rgb) {
+        match index {
+            PaletteIndex::Indexed(i) => self.indexed[i as usize] = color,
+            PaletteIndex::Foreground => self.foreground = color,
+            PaletteIndex::Background => self.background = color,
+            PaletteIndex::Cursor => self.cursor = color,
+        }
+    }
+}
+
+impl Default for Colors {
+    /// The xterm default palette.
+    fn default() -> Self {
+        const BASE: [Rgb; 16] = [
+            Rgb(0x00, 0x00, 0x00),
+            Rgb(0xcd, 0x00, 0x00),
+            Rgb(0x00, 0xcd, 0x00),
+            Rgb(0xcd, 0xcd, 0x00),
+            Rgb(0x00, 0x00, 0xee),
+            Rgb(0xcd, 0x00, 0xcd),
+            Rgb(0x00, 0xcd, 0xcd),
+            Rgb(0xe5, 0xe5, 0xe5),
+            Rgb(0x7f, 0x7f, 0x7f),
+            Rgb(0xff, 0x00, 0x00),
+            Rgb(0x00, 0xff, 0x00),
+            Rgb(0xff, 0xff, 0x00),
+            Rgb(0x5c, 0x5c, 0xff),
+            Rgb(0xff, 0x00, 0xff),
+            Rgb(0x00, 0xff, 0xff),
+            Rgb(0xff, 0xff, 0xff),
+        ];
+
+        let mut colors = [Rgb(0, 0, 0); 256];
+        colors[..16].copy_from_slice(&BASE);
+        // 6x6x6 color cube
+        let level = |v: usize| if v == 0 { 0 } else { (v * 40 + 55) as u8 };
+        for i in 0..216 {
+            colors[16 + i] = Rgb(level(i / 36), level(i / 6 % 6), level(i % 6));
+        }
+        // grayscale ramp
+        for i in 0..24 {
+            let v = (8 + i * 10) as u8;
+            colors[232 + i] = Rgb(v, v, v);
+        }
+
+        Self {
+            indexed: colors,
+            foreground: BASE[7],
+            background: BASE[0],
+            cursor: BASE[7],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_color_specs() {
+        assert_eq!(Rgb::parse(b"rgb:ffff/8080/0000"), Some(Rgb(0xff, 0x80, 0x00)));
+        assert_eq!(Rgb::parse(b"rgb:f/8/0"), Some(Rgb(0xff, 0x88, 0x00)));
+        assert_eq!(Rgb::parse(b"#ff8000"), Some(Rgb(0xff, 0x80, 0x00)));
+        assert_eq!(Rgb::parse(b"#f80"), Some(Rgb(0xf0, 0x80, 0x00)));
+        assert_eq!(Rgb::parse(b"rgb:ff/80"), None);
+        assert_eq!(Rgb::parse(b"red"), None);
+    }
+
+    #[test]
+    fn parse_host_replies() {
+        let replies = b"\x1b]4;1;rgb:aaaa/0000/0000\x07\x1b]4;300;rgb:0/0/0\x07\
+            \x1b]11;rgb:1111/2222/3333\x1b\\\x1b[?62;22c";
+        assert_eq!(
+            parse_replies(replies),
+            vec![
+                (PaletteIndex::Indexed(1), Rgb(0xaa, 0, 0)),
+                (PaletteIndex::Background, Rgb(0x11, 0x22, 0x33)),
+            ]
+        );
+    }
+
+    #[test]
+    fn reset_to_the_host_colors() {
+        let background = Rgb(0x11, 0x22, 0x33);
+        let mut palette = Palette::new(&[(PaletteIndex::Background, background)]);
+        assert_eq!(palette.get(PaletteIndex::Background), background);
+
+        palette.set(PaletteIndex::Background, Some(Rgb(0, 0, 0)));
+        palette.set(PaletteIndex::Background, None);
+        assert_eq!(palette.get(PaletteIndex::Background), background);
+        palette.set(PaletteIndex::Indexed(1), None);
+        assert_eq!(palette.get(PaletteIndex::Indexed(1)), Rgb(0xcd, 0, 0));
+    }
+
+    #[test]
+    fn changes_from_the_initial_palette() {
+        let mut palette = Palette::new(&[(PaletteIndex::Background, Rgb(1, 2, 3))]);
+        let set = |palette: &Palette| -> Vec<_> {
+            palette.changes().into_iter().filter(|c| c.color.is_some()).collect()
+        };
+        // the viewers get the host's background, and keep their own defaults for the rest.
+        let background = PaletteChange {
+            index: PaletteIndex::Background,
+            color: Some(Rgb(1, 2, 3)),
+        };
+        assert_eq!(set(&palette), [background]);
+
+        palette.set(PaletteIndex::Indexed(3), Some(Rgb(1, 2, 3)));
+        palette.set(PaletteIndex::Background, Some(Rgb(0, 0, 0)));
+        palette.set(PaletteIndex::Background, None);
+        let indexed = PaletteChange {
+            index: PaletteIndex::Indexed(3),
+            color: Some(Rgb(1, 2, 3)),
+        };
+        assert_eq!(set(&palette), [indexed, background]);
+    }
+}
@@ -0,0 +1,36 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// Records the messages sent to the viewers in a file.
+///
+/// Each record is the number of milliseconds since the start of the recording, as a big endian
+/// `u64`, followed by the message as it is sent on the network.
+pub struct Recorder {
+    file: BufWriter<File>,
+    path: PathBuf,
+    start: Instant,
+}
+
+impl Recorder {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            file: BufWriter::new(File::create(path)?),
+            path: path.to_owned(),
+            start: Instant::now(),
+        })
+    }
+
+    pub fn record(&mut self, message: &[u8]) -> io::Result<()> {
+        let elapsed = self.start.elapsed().as_millis() as u64;
+        self.file.write_all(&elapsed.to_be_bytes())?;
+        self.file.write_all(message)
+    }
+
+    /// Ends the recording, returning the path of the file.
+    pub fn finish(mut self) -> io::Result<PathBuf> {
+        self.file.flush()?;
+        Ok(self.path)
+    }
+}
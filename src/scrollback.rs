@@ -0,0 +1,78 @@
+use std::collections::VecDeque;
+use std::ops::Range;
+
+use crate::cell::Cell;
+
+/// The rows that scrolled off the top of the screen, by absolute line, so that the output of a
+/// command can still be read once it is off screen. The oldest rows are dropped past `capacity`.
+pub struct Scrollback {
+    rows: VecDeque<Vec<Cell>>,
+    /// absolute line of the first row kept.
+    first_line: usize,
+    capacity: usize,
+}
+
+impl Scrollback {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            rows: VecDeque::new(),
+            first_line: 0,
+            capacity,
+        }
+    }
+
+    /// Adds the row that just scrolled off the screen.
+    pub fn push(&mut self, row: Vec<Cell>) {
+        if self.capacity == 0 {
+            self.first_line += 1;
+            return;
+        }
+        if self.rows.len() == self.capacity {
+            self.rows.pop_front();
+            self.first_line += 1;
+        }
+        self.rows.push_back(row);
+    }
+
+    /// Returns the absolute lines that are kept.
+    pub fn lines(&self) -> Range<usize> {
+        self.first_line..self.first_line + self.rows.len()
+    }
+
+    pub fn get(&self, line: usize) -> Option<&[Cell]> {
+        let index = line.checked_sub(self.first_line)?;
+        self.rows.get(index).map(Vec::as_slice)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(symbol: char) -> Vec<Cell> {
+        let mut cell = Cell::default();
+        cell.set_symbol(symbol);
+        vec![cell; 2]
+    }
+
+    #[test]
+    fn drops_the_oldest_rows() {
+        let mut scrollback = Scrollback::new(2);
+        for symbol in ['a', 'b', 'c'] {
+            scrollback.push(row(symbol));
+        }
+        assert_eq!(scrollback.lines(), 1..3);
+        assert_eq!(scrollback.get(0), None);
+        assert_eq!(scrollback.get(1), Some(&row('b')[..]));
+        assert_eq!(scrollback.get(2), Some(&row('c')[..]));
+        assert_eq!(scrollback.get(3), None);
+    }
+
+    #[test]
+    fn counts_lines_without_capacity() {
+        let mut scrollback = Scrollback::new(0);
+        scrollback.push(row('a'));
+        assert_eq!(scrollback.lines(), 1..1);
+        assert_eq!(scrollback.get(0), None);
+    }
+}
@@ -0,0 +1,35 @@
+/// A command run by the shell, delimited by the OSC 133 shell integration marks.
+///
+/// Lines are absolute: they count every line since the start of the cast, including the ones
+/// that have scrolled off the screen, so they stay valid as the screen scrolls.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CommandEvent {
+    /// Line of the prompt start mark (`133;A`).
+    pub prompt_line: usize,
+    /// Line of the output start mark (`133;C`), if the command produced one.
+    pub output_line: Option<usize>,
+    /// Line of the command end mark (`133;D`).
+    pub end_line: usize,
+    /// The command line, as read from the screen between the `133;B` and `133;C` marks.
+    pub command: String,
+    /// Working directory last reported with OSC 7.
+    pub cwd: Option<String>,
+    pub exit_code: Option<i32>,
+}
+
+/// Extracts the path from an OSC 7 `file://host/path` url, decoding percent escapes.
+pub fn parse_cwd(url: &[u8]) -> Option<String> {
+    let url = url.strip_prefix(b"file://")?;
+    let path = &url[url.iter().position(|&b| b == b'/')?..];
+    let mut decoded = Vec::with_capacity(path.len());
+    let mut bytes = path.iter();
+    while let Some(&b) = bytes.next() {
+        if b == b'%' {
+            let hex = [*bytes.next()?, *bytes.next()?];
+            decoded.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            decoded.push(b);
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
@@ -0,0 +1,224 @@
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use crate::cell::Cell;
+use crate::host::Privacy;
+use crate::layout::Rect;
+use crate::style::{Modifier, Style};
+
+/// How long a message stays in the status line.
+const MESSAGE_DURATION: Duration = Duration::from_secs(5);
+/// Most viewer names shown, the others being counted.
+const MAX_NAMES: usize = 3;
+
+/// Session information shown on the host screen, below the casted area.
+pub struct Status {
+    pub addr: SocketAddr,
+    pub start: Instant,
+    /// names of the connected viewers.
+    pub viewers: Vec<String>,
+    pub recording: bool,
+    pub title: Option<String>,
+    /// frames drawn during the last second.
+    pub fps: u32,
+    /// how the broadcast is paused, if it is.
+    pub paused: Option<Privacy>,
+    /// the viewer that has the floor, if the host doesn't.
+    pub floor: Option<String>,
+    /// whether the program is reading a password or some other input that isn't echoed.
+    pub secret_input: bool,
+    /// whether the viewers' keys are kept from the pty.
+    pub input_locked: bool,
+    /// shown instead of the session information while a command is typed.
+    pub prompt: Option<String>,
+    /// messages to show, one after the other.
+    messages: Vec<String>,
+    /// when the first message was shown.
+    shown: Instant,
+}
+
+impl Status {
+    pub fn new(addr: SocketAddr) -> Self {
+        Self {
+            addr,
+            start: Instant::now(),
+            viewers: Vec::new(),
+            recording: false,
+            title: None,
+            fps: 0,
+            paused: None,
+            floor: None,
+            secret_input: false,
+            input_locked: false,
+            prompt: None,
+            messages: Vec::new(),
+            shown: Instant::now(),
+        }
+    }
+
+    /// Shows `message` instead of the session information for a few seconds.
+    pub fn notify(&mut self, message: String) {
+        self.notify_all(vec![message]);
+    }
+
+    /// Shows the messages one after the other, instead of the ones being shown.
+    pub fn notify_all(&mut self, messages: Vec<String>) {
+        self.messages = messages;
+        self.shown = Instant::now();
+    }
+
+    fn text(&self) -> String {
+        if let Some(ref prompt) = self.prompt {
+            return prompt.clone();
+        }
+        let shown = self.shown.elapsed().as_secs_f64() / MESSAGE_DURATION.as_secs_f64();
+        if let Some(message) = self.messages.get(shown as usize) {
+            return format!(" {}", message);
+        }
+        let uptime = self.start.elapsed().as_secs();
+        let paused = self
+            .paused
+            .map_or(String::new(), |privacy| format!(" (paused, {})", privacy));
+        let floor = self.floor.as_deref().unwrap_or("host");
+        let names = match self.viewers.len() {
+            0 => String::new(),
+            n if n <= MAX_NAMES => format!(": {}", self.viewers.join(", ")),
+            n => format!(": {}, +{}", self.viewers[..MAX_NAMES].join(", "), n - MAX_NAMES),
+        };
+        let secret_input = if self.secret_input { " | secret input" } else { "" };
+        let locked = if self.input_locked { " | input locked" } else { "" };
+        let mut text = format!(
+            " {} viewer{}{}{} | floor: {}{}{} | {} | up {}h{:02}m{:02}s | {} | {} fps",
+            self.viewers.len(),
+            if self.viewers.len() == 1 { "" } else { "s" },
+            names,
+            paused,
+            floor,
+            locked,
+            secret_input,
+            self.addr,
+            uptime / 3600,
+            uptime / 60 % 60,
+            uptime % 60,
+            if self.recording { "recording" } else { "not recording" },
+            self.fps,
+        );
+        if let Some(ref title) = self.title {
+            text.push_str(" | ");
+            text.push_str(title);
+        }
+        text
+    }
+
+    /// Returns the cells of the border around `rect` and of the status line below it, in host
+    /// screen coordinates. Only the parts that fit on a `width` x `height` screen are returned.
+    pub fn cells(&self, rect: &Rect, width: usize, height: usize) -> Vec<(usize, usize, Cell)> {
+        let mut cells = Vec::new();
+        let left = rect.x.checked_sub(1);
+        let top = rect.y.checked_sub(1);
+        let right = Some(rect.x + rect.width).filter(|&x| x < width);
+        let bottom = Some(rect.y + rect.height).filter(|&y| y < height);
+        let mut put = |x: Option<usize>, y: Option<usize>, symbol: char| {
+            if let (Some(x), Some(y)) = (x, y) {
+                cells.push((
+                    x,
+                    y,
+                    Cell {
+                        symbol,
+                        style: Style::default(),
+                    },
+                ));
+            }
+        };
+
+        for x in rect.x..rect.x + rect.width {
+            put(Some(x), top, '─');
+            put(Some(x), bottom, '─');
+        }
+        for y in rect.y..rect.y + rect.height {
+            put(left, Some(y), '│');
+            put(right, Some(y), '│');
+        }
+        put(left, top, '┌');
+        put(right, top, '┐');
+        put(left, bottom, '└');
+        put(right, bottom, '┘');
+
+        let line = match bottom {
+            Some(y) if y + 1 < height => y + 1,
+            _ => return cells,
+        };
+        let start = left.unwrap_or(rect.x);
+        let end = right.map_or(rect.x + rect.width, |x| x + 1);
+        let style = Style {
+            modifier: Modifier::REVERSED,
+            ..Style::default()
+        };
+        let text = self.text();
+        let mut text = text.chars();
+        for x in start..end {
+            let symbol = text.next().unwrap_or(' ');
+            cells.push((x, line, Cell { symbol, style }));
+        }
+        cells
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status() -> Status {
+        Status::new("127.0.0.1:9999".parse().unwrap())
+    }
+
+    fn symbol(cells: &[(usize, usize, Cell)], x: usize, y: usize) -> Option<char> {
+        cells
+            .iter()
+            .find(|&&(cx, cy, _)| (cx, cy) == (x, y))
+            .map(|(_, _, cell)| cell.symbol)
+    }
+
+    #[test]
+    fn border_and_status_line() {
+        let cells = status().cells(&Rect::new(1, 1, 3, 2), 10, 5);
+        assert_eq!(symbol(&cells, 0, 0), Some('┌'));
+        assert_eq!(symbol(&cells, 4, 0), Some('┐'));
+        assert_eq!(symbol(&cells, 0, 3), Some('└'));
+        assert_eq!(symbol(&cells, 4, 3), Some('┘'));
+        assert_eq!(symbol(&cells, 2, 0), Some('─'));
+        assert_eq!(symbol(&cells, 0, 2), Some('│'));
+        // the status line is as wide as the border.
+        let line: String = (0..5).filter_map(|x| symbol(&cells, x, 4)).collect();
+        assert_eq!(line, " 0 vi");
+        assert_eq!(symbol(&cells, 5, 4), None);
+    }
+
+    #[test]
+    fn nothing_outside_the_screen() {
+        assert!(status().cells(&Rect::new(0, 0, 10, 5), 10, 5).is_empty());
+        // no room for the status line below the border.
+        let cells = status().cells(&Rect::new(1, 1, 3, 2), 10, 4);
+        assert!(cells.iter().all(|&(x, y, _)| x < 10 && y < 4));
+        assert_eq!(symbol(&cells, 0, 3), Some('└'));
+    }
+
+    #[test]
+    fn prompt_then_messages_then_session() {
+        let mut status = status();
+        status.viewers = vec!["alice".to_string(), "bob".to_string()];
+        status.paused = Some(Privacy::Curtain);
+        let session = " 2 viewers: alice, bob (paused, curtain) | floor: host";
+        assert!(status.text().starts_with(session));
+
+        status.viewers.extend(["carol", "dave", "erin"].iter().map(|name| name.to_string()));
+        let session = " 5 viewers: alice, bob, carol, +2 (paused, curtain) | floor: host";
+        assert!(status.text().starts_with(session));
+
+        status.notify("hello".to_string());
+        assert_eq!(status.text(), " hello");
+
+        status.prompt = Some("command: ".to_string());
+        assert_eq!(status.text(), "command: ");
+    }
+}
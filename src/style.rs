@@ -1,3 +1,5 @@
+use log::debug;
+
 #[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Style {
     pub fg: Color,
@@ -75,7 +77,7 @@ impl Style {
             106 => self.bg = { self.set_bold(); Color::Cyan },
             107 => self.bg = { self.set_bold(); Color::White },
 
-            _ => unreachable!()
+            _ => debug!("not a 3 bits color: {}", color),
         }
     }
 }
@@ -96,9 +98,10 @@ bitflags! {
     }
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
 #[allow(dead_code)]
 pub enum Color {
+    #[default]
     Reset,
     Black,
     Red,
@@ -119,9 +122,3 @@ pub enum Color {
     Rgb(u8, u8, u8),
     Indexed(u8),
 }
-
-impl Default for Color {
-    fn default() -> Self {
-        Color::Reset
-    }
-}
@@ -1,13 +1,29 @@
 use std::io;
 use std::ops::Range;
+use std::time::{Duration, Instant};
 
 use log::debug;
 
 use crate::backends::Backend;
 use crate::buffer::Buffer;
 use crate::cell::Cell;
+use crate::clipboard::{Clipboard, ClipboardPolicy};
+use crate::frame::Frame;
 use crate::layout::Rect;
-use crate::style::Style;
+use crate::mode::Mode;
+use crate::palette::{Palette, PaletteChange, PaletteIndex, Rgb};
+use crate::scrollback::Scrollback;
+use crate::shell::{self, CommandEvent};
+use crate::style::{Color, Style};
+
+/// How long a synchronized update (DECSET 2026) can hold back drawing, in case the program never
+/// ends it.
+const SYNCHRONIZED_UPDATE_TIMEOUT: Duration = Duration::from_millis(200);
+
+const TAB_WIDTH: usize = 8;
+
+/// Number of rows kept once they scrolled off the screen.
+const SCROLLBACK_LINES: usize = 10_000;
 
 pub struct Terminal<B: Backend> {
     c_style: Style,
@@ -17,21 +33,148 @@ pub struct Terminal<B: Backend> {
     c_col: usize,
     pub backend: B,
     scroll_range: Range<usize>,
+    clipboard_policy: ClipboardPolicy,
+    allow_clipboard_read: bool,
+    clipboard: Option<Clipboard>,
+    palette: Palette,
+    palette_changes: Vec<PaletteChange>,
+    /// bytes to send back to the program, in answer to queries.
+    replies: Vec<u8>,
+    /// number of lines that have scrolled off the top of the screen.
+    scrolled_lines: usize,
+    scrollback: Scrollback,
+    cwd: Option<String>,
+    /// the command currently being typed or run, if the shell reports them with OSC 133.
+    command: Option<CommandEvent>,
+    /// (line, column) where the command input starts.
+    command_input: Option<(usize, usize)>,
+    commands: Vec<CommandEvent>,
+    /// the commands whose output is still on the screen or in the scrollback.
+    history: Vec<CommandEvent>,
+    mode: Mode,
+    /// modes currently enabled on the host terminal.
+    host_mode: Mode,
+    /// start of the current synchronized update.
+    synchronized_since: Option<Instant>,
+    /// part of the grid shown on the host screen, at the position of `rect`, when the host
+    /// terminal is too small for the whole cast. It follows the cursor.
+    viewport: Option<Rect>,
+    /// width of the host screen, if the cast can be scrolled on it.
+    host_width: Option<usize>,
+    /// window title set with OSC 0 or 2.
+    title: Option<String>,
+    /// whether the whole grid must be drawn on the next draw.
+    invalidated: bool,
 }
 
 impl<B: Backend> Terminal<B> {
-    pub fn new(rect: Rect, backend: B) -> Terminal<B> {
+    pub fn new(mut rect: Rect, backend: B) -> Terminal<B> {
+        // the cursor must always be somewhere on the grid.
+        rect.width = rect.width.max(1);
+        rect.height = rect.height.max(1);
         Terminal {
-            scroll_range: 0..rect.height as usize,
+            scroll_range: 0..rect.height,
             buffer: Buffer::new(rect.clone()),
             rect,
             c_style: Style::default(),
             c_col: 0,
             c_row: 0,
             backend,
+            clipboard_policy: ClipboardPolicy::default(),
+            allow_clipboard_read: false,
+            clipboard: None,
+            palette: Palette::default(),
+            palette_changes: Vec::new(),
+            replies: Vec::new(),
+            scrolled_lines: 0,
+            scrollback: Scrollback::new(SCROLLBACK_LINES),
+            cwd: None,
+            command: None,
+            command_input: None,
+            commands: Vec::new(),
+            history: Vec::new(),
+            mode: Mode::default(),
+            host_mode: Mode::default(),
+            synchronized_since: None,
+            viewport: None,
+            host_width: None,
+            title: None,
+            invalidated: false,
+        }
+    }
+
+    /// Lets the draws scroll the host screen, which they only do when the cast is as wide as it.
+    pub fn set_host_width(&mut self, width: usize) {
+        self.host_width = Some(width);
+    }
+
+    /// Only shows `width` x `height` cells of the grid on the host screen, around the cursor.
+    pub fn set_viewport(&mut self, width: usize, height: usize) {
+        self.viewport = Some(Rect::new(0, 0, width.max(1), height.max(1)));
+    }
+
+    /// Returns the part of the host screen where the grid is shown, and the cell of the grid at
+    /// its top left corner.
+    pub fn visible(&self) -> (Rect, (usize, usize)) {
+        match self.viewport {
+            Some(ref v) => (
+                Rect::new(self.rect.x, self.rect.y, v.width, v.height),
+                (v.x, v.y),
+            ),
+            None => (self.rect.clone(), (0, 0)),
         }
     }
 
+    /// Whether the program is in the middle of a synchronized update, in which case the screen
+    /// is likely incomplete and should not be drawn.
+    pub fn is_synchronized(&self) -> bool {
+        self.synchronized_since
+            .is_some_and(|since| since.elapsed() < SYNCHRONIZED_UPDATE_TIMEOUT)
+    }
+
+    pub fn rect(&self) -> &Rect {
+        &self.rect
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    /// Mirrors the mode changes of the program on the host terminal, so that paste, focus and
+    /// mouse events are reported to the program the way it expects them.
+    pub async fn apply_modes(&mut self) -> io::Result<()> {
+        let changed = self.mode ^ self.host_mode;
+        if changed.is_empty() {
+            return Ok(());
+        }
+        self.backend.set_mode(changed & self.mode, true).await?;
+        self.backend.set_mode(changed & self.host_mode, false).await?;
+        self.backend.flush().await?;
+        self.host_mode = self.mode;
+        Ok(())
+    }
+
+    /// Returns the pending answers to the program's queries, that should be written to the pty.
+    pub fn take_replies(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.replies)
+    }
+
+    /// Sets how OSC 52 clipboard writes are handled. Clipboard reads are only forwarded to the
+    /// host when `allow_read` is set, and never to the viewers.
+    pub fn set_clipboard_policy(&mut self, policy: ClipboardPolicy, allow_read: bool) {
+        self.clipboard_policy = policy;
+        self.allow_clipboard_read = allow_read;
+    }
+
+    /// Starts with the colors of `palette`, that the programs reset the entries to.
+    pub fn set_initial_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
     #[inline]
     fn width(&self) -> usize {
         self.rect.width
@@ -49,58 +192,36 @@ impl<B: Backend> Terminal<B> {
 
     #[inline]
     fn set_row(&mut self, row: usize) {
-        assert!(
-            row < self.height(),
-            "row out of bounds: {} >= {}",
-            row,
-            self.height()
-        );
-        self.c_row = row;
-    }
-
-    #[inline]
-    fn col(&self) -> usize {
-        self.c_col
+        if row >= self.height() {
+            debug!("row out of bounds: {} >= {}", row, self.height());
+        }
+        self.c_row = std::cmp::min(row, self.height() - 1);
     }
 
+    /// absolute line of the cursor, counting the lines that scrolled off the screen.
     #[inline]
-    fn set_col(&mut self, col: usize) {
-        assert!(
-            col < self.width(),
-            "col out of bounds: {} >= {}",
-            col,
-            self.width()
-        );
-        self.c_col = col;
+    fn line(&self) -> usize {
+        self.scrolled_lines + self.row()
     }
 
-    /// index of the start of the current line
     #[inline]
-    fn current_line_index(&self) -> usize {
-        self.row() * self.width()
+    fn col(&self) -> usize {
+        self.c_col
     }
 
     #[inline]
-    fn scroll_range_start_index(&self) -> usize {
-        self.scroll_range.start * self.width()
+    fn set_col(&mut self, col: usize) {
+        if col >= self.width() {
+            debug!("col out of bounds: {} >= {}", col, self.width());
+        }
+        self.c_col = std::cmp::min(col, self.width() - 1);
     }
 
+    /// The cursor column, clamped to the grid. The cursor sits past the last column after
+    /// printing to it, until the next character wraps.
     #[inline]
-    fn scroll_range_end_index(&self) -> usize {
-        self.scroll_range.end * self.width() - 1
-    }
-
-    fn index_of(&self, x: usize, y: usize) -> usize {
-        let width = self.rect.width;
-        debug!(
-            "getting position ({}, {}), width = {}, height = {}, index = {}",
-            x,
-            y,
-            width,
-            self.rect.height,
-            (y * self.rect.width + x) as usize
-        );
-        (y * width + x) as usize
+    fn clamped_col(&self) -> usize {
+        std::cmp::min(self.col(), self.width() - 1)
     }
 
     fn move_up(&mut self, n: usize) {
@@ -111,19 +232,19 @@ impl<B: Backend> Terminal<B> {
 
     fn move_down(&mut self, n: usize) {
         debug!("move down: {}", n);
-        let n_row = std::cmp::min(self.height() - 1, self.row() + n);
+        let n_row = self.row().saturating_add(n);
         self.set_row(n_row);
     }
 
     fn move_backward(&mut self, n: usize) {
         debug!("move back: {}", n);
-        let n_col = self.col().saturating_sub(n);
+        let n_col = self.clamped_col().saturating_sub(n);
         self.set_col(n_col);
     }
 
     fn move_forward(&mut self, n: usize) {
         debug!("move forward: {}", n);
-        let n_col = std::cmp::min(self.width() - 1, self.col() + n);
+        let n_col = self.col().saturating_add(n);
         self.set_col(n_col);
     }
 
@@ -139,79 +260,56 @@ impl<B: Backend> Terminal<B> {
         self.set_row(y);
     }
 
-    fn current_index(&self) -> usize {
-        self.row() * self.width() + self.col()
-    }
-
     fn insert_line(&mut self, n: usize) {
         debug!("inserting {} lines", n);
-        let to_remove_end = self.scroll_range_end_index();
-        let to_remove_start = to_remove_end - n * self.width();
-        self.buffer.drain(to_remove_start..to_remove_end);
-        let to_insert_start = self.current_line_index();
-        let amount = n * self.width();
-        self.buffer.splice(
-            to_insert_start..to_insert_start,
-            (0..amount).map(|_| Cell::default()),
-        );
+        if !self.scroll_range.contains(&self.row()) {
+            return;
+        }
+        self.buffer
+            .scroll_down(self.row()..self.scroll_range.end, n);
     }
 
-    fn delete_lines(&mut self, num: usize) {
-        debug!("delete lines: {}", num);
-        let start = self.current_line_index();
-        let amount = std::cmp::min(self.scroll_range_end_index() - start, num * self.width());
-        let end = start + amount;
-        let len_before = self.buffer.len();
-        let index = self.scroll_range_end_index();
-        self.buffer
-            .splice(index..index, (0..amount).map(|_| Cell::default()));
-        self.buffer.drain(start..end);
-        assert_eq!(len_before, self.buffer.len());
+    fn delete_lines(&mut self, n: usize) {
+        debug!("delete lines: {}", n);
+        if !self.scroll_range.contains(&self.row()) {
+            return;
+        }
+        self.buffer.scroll_up(self.row()..self.scroll_range.end, n);
     }
 
     fn clear_line(&mut self, mode: LineClearMode) {
         debug!("clearing line {:?}", mode);
-        let (start, end) = match mode {
-            LineClearMode::Right => {
-                let start = self.current_index();
-                let end = self.index_of(self.width() - 1, self.row());
-                (start, end)
-            }
-            LineClearMode::Left => {
-                let start = self.index_of(0, self.row());
-                let end = self.current_index();
-                (start, end)
-            }
-            LineClearMode::All => {
-                let start = self.index_of(0, self.row());
-                let end = self.index_of(self.width() - 1, self.row());
-                (start, end)
-            }
+        let col = self.clamped_col();
+        let row = self.buffer.row_mut(self.row());
+        let cells = match mode {
+            LineClearMode::Right => &mut row[col..],
+            LineClearMode::Left => &mut row[..=col],
+            LineClearMode::All => row,
         };
-        self.buffer[start..=end].iter_mut().for_each(|c| {
+        cells.iter_mut().for_each(|c| {
             c.reset();
         });
     }
 
+    fn clear_rows(&mut self, rows: Range<usize>) {
+        for y in rows {
+            self.buffer.row_mut(y).iter_mut().for_each(|cell| {
+                cell.reset();
+            });
+        }
+    }
+
     fn clear_screen(&mut self, mode: ClearMode) {
         debug!("clear: {:?}", mode);
         match mode {
-            ClearMode::All => {
-                self.buffer.iter_mut().for_each(|cell| {
-                    cell.reset();
-                });
-            }
+            ClearMode::All => self.clear_rows(0..self.height()),
             ClearMode::Above => {
-                let index = self.current_index();
-                self.buffer[..=index].iter_mut().for_each(|cell| {
-                    cell.reset();
-                });
+                self.clear_rows(0..self.row());
+                self.clear_line(LineClearMode::Left);
             }
             ClearMode::Below => {
-                let index = self.current_index();
-                self.buffer[index..].iter_mut().for_each(|cell| {
-                    cell.reset();
-                });
+                self.clear_line(LineClearMode::Right);
+                self.clear_rows(self.row() + 1..self.height());
             }
             mode => {
                 debug!("unhandled clear mode: {:?}", mode);
@@ -219,9 +317,43 @@ impl<B: Backend> Terminal<B> {
         }
     }
 
-    fn set_scroll_range(&mut self, start: usize, end: Option<usize>) {
-        debug!("set scroll range: {}..{:?}", start, end);
-        self.scroll_range = start..end.unwrap_or(self.height());
+    /// DECSTBM, `top` and `bottom` are 1-based and inclusive.
+    fn set_scroll_range(&mut self, top: usize, bottom: Option<usize>) {
+        debug!("set scroll range: {}..{:?}", top, bottom);
+        let start = top.saturating_sub(1);
+        let end = std::cmp::min(bottom.unwrap_or(self.height()), self.height());
+        if start + 1 >= end {
+            debug!("invalid scroll range: {}..{}", start, end);
+            return;
+        }
+        self.scroll_range = start..end;
+        self.cursor_goto(0, 0);
+    }
+
+    fn set_private_mode(&mut self, number: u16, enable: bool) {
+        if number == 2026 {
+            debug!("synchronized update: {}", enable);
+            self.synchronized_since = if enable { Some(Instant::now()) } else { None };
+            return;
+        }
+        match Mode::from_number(number) {
+            Some(mode) => {
+                debug!("set mode {:?}: {}", mode, enable);
+                self.mode.set(mode, enable);
+            }
+            None => debug!("[unhandled private mode] {}: {}", number, enable),
+        }
+    }
+
+    /// DECRQM, reports whether a private mode is set (1), reset (2) or unknown (0).
+    fn report_private_mode(&mut self, number: u16) {
+        let state = match (number, Mode::from_number(number)) {
+            (2026, _) => 2 - self.is_synchronized() as u8,
+            (_, Some(mode)) => 2 - self.mode.contains(mode) as u8,
+            (_, None) => 0,
+        };
+        let reply = format!("\x1b[?{};{}$y", number, state);
+        self.replies.extend_from_slice(reply.as_bytes());
     }
 
     fn backspace(&mut self) {
@@ -239,60 +371,41 @@ impl<B: Backend> Terminal<B> {
             "inc row, c_row: {}, range_end: {}",
             self.c_row, self.scroll_range.end
         );
-        if self.c_row < self.scroll_range.end - 1 {
-            self.c_row += 1;
-        } else {
-            debug!("here");
+        if self.c_row + 1 == self.scroll_range.end {
             //row remains the same but the viewport is shifted up, ie rmove the first line
-            let width = self.width();
-            let start = (self.scroll_range.start) * width;
-            let end = (self.scroll_range.end - 1) * width;
-            debug!("width: {}, start: {}, end: {}", width, start, end);
-            self.buffer.drain(start..start + width);
-            self.buffer
-                .splice(end..end, (0..width).map(|_| Cell::default()));
+            if self.scroll_range.start == 0 {
+                self.scrollback.push(self.buffer.row(0).to_vec());
+                self.scrolled_lines += 1;
+            }
+            self.buffer.scroll_up(self.scroll_range.clone(), 1);
+        } else if self.c_row + 1 < self.height() {
+            self.c_row += 1;
         }
     }
 
     fn dec_row(&mut self) {
         debug!("dec row");
-        let mut n_row = self.row().saturating_sub(1);
-        if n_row < self.scroll_range.start {
-            self.scroll_up();
-            n_row = self.scroll_range.start;
+        if self.row() == self.scroll_range.start {
+            self.scroll_down();
+        } else {
+            self.set_row(self.row().saturating_sub(1));
         }
-        self.set_row(n_row);
     }
 
-    fn scroll_up(&mut self) {
-        debug!("scroll up");
-        let len_before = self.buffer.len();
-        let end = self.scroll_range_end_index();
-        let start = end - self.width();
-        self.buffer.drain(start..end);
-        let index = self.scroll_range_start_index();
-        let width = self.width();
-        self.buffer
-            .splice(index..index, (0..width).map(|_| Cell::default()));
-        assert_eq!(len_before, self.buffer.len());
+    /// Shifts the scroll region down, inserting a blank line at its top.
+    fn scroll_down(&mut self) {
+        debug!("scroll down");
+        self.buffer.scroll_down(self.scroll_range.clone(), 1);
     }
 
     fn dec_col(&mut self) {
-        if self.col() == 0 {
-            let n_col = self.width() - 1;
-            debug!("dec col: {}", n_col);
-            self.scroll_up();
-            self.set_col(n_col);
-        } else {
-            let n_col = self.col().wrapping_sub(1);
-            debug!("dec col: {}", n_col);
-            self.set_col(n_col);
-        }
+        let n_col = self.clamped_col().saturating_sub(1);
+        debug!("dec col: {}", n_col);
+        self.set_col(n_col);
     }
 
     fn current_cell_mut(&mut self) -> Option<&mut Cell> {
-        let index = self.index_of(self.col(), self.row());
-        self.buffer.get_mut(index)
+        self.buffer.cell_mut(self.col(), self.row())
     }
 
     fn put_char(&mut self, c: char) {
@@ -301,24 +414,21 @@ impl<B: Backend> Terminal<B> {
             self.c_col = 0;
             self.inc_row();
         }
-        let style = self.c_style.clone();
-        let c_row = self.c_row;
-        let c_col = self.c_col;
-        let cell = self.current_cell_mut().expect(&format!(
-            "error with getting current cell: ({}, {})",
-            c_col, c_row
-        ));
-        cell.set_symbol(c);
-        cell.set_style(style);
+        let style = self.c_style;
+        match self.current_cell_mut() {
+            Some(cell) => {
+                cell.set_symbol(c);
+                cell.set_style(style);
+            }
+            None => debug!("no cell at ({}, {})", self.c_col, self.c_row),
+        }
         self.c_col += 1;
     }
 
     fn put_tab(&mut self) {
         debug!("put tab");
-        for i in self.c_col..(std::cmp::max(self.rect.width, self.c_col + self.c_col % 4)) {
-            let index = self.index_of(i, self.c_row);
-            self.buffer[index].reset();
-        }
+        let n_col = (self.clamped_col() / TAB_WIDTH + 1) * TAB_WIDTH;
+        self.set_col(n_col);
     }
 
     fn linefeed(&mut self) {
@@ -331,21 +441,397 @@ impl<B: Backend> Terminal<B> {
         self.dec_row();
     }
 
+    /// SGR, colors and text attributes.
+    fn set_graphic_rendition(&mut self, params: &vte::Params) {
+        if params.is_empty() {
+            self.c_style.reset();
+            return;
+        }
+        let mut params = iter_params(params);
+        while let Some(param) = params.next() {
+            match param {
+                [0] => {
+                    self.c_style.reset();
+                }
+                [1] => {
+                    self.c_style.set_bold();
+                }
+                [3] => {
+                    self.c_style.set_italic();
+                }
+                [23] => {
+                    self.c_style.unset_italic();
+                }
+                [24] => {
+                    self.c_style.unset_underline();
+                }
+                // extended colors, `38;5;n` and `38;2;r;g;b`, or with colons, `38:5:n`...
+                [38] => {
+                    let mut color = params.by_ref().map(|p| p[0]);
+                    match parse_extended_color(&mut color) {
+                        Some(color) => self.c_style.fg = color,
+                        None => debug!("invalid foreground color"),
+                    }
+                }
+                [38, sub @ ..] => match parse_extended_color(&mut colon_color_params(sub)) {
+                    Some(color) => self.c_style.fg = color,
+                    None => debug!("invalid foreground color: {:?}", sub),
+                },
+                [39] => {
+                    self.c_style.fg = Color::default();
+                }
+                [48] => {
+                    let mut color = params.by_ref().map(|p| p[0]);
+                    match parse_extended_color(&mut color) {
+                        Some(color) => self.c_style.bg = color,
+                        None => debug!("invalid background color"),
+                    }
+                }
+                [48, sub @ ..] => match parse_extended_color(&mut colon_color_params(sub)) {
+                    Some(color) => self.c_style.bg = color,
+                    None => debug!("invalid background color: {:?}", sub),
+                },
+                [49] => {
+                    self.c_style.bg = Color::default();
+                }
+                [color @ (30..=37 | 40..=47 | 90..=97 | 100..=107)] => {
+                    self.c_style.set_color_3bits(*color as usize)
+                }
+                param => debug!("[unhandled sgr] {:?}", param),
+            }
+        }
+    }
+
     fn bell(&mut self) {
         debug!("Bell!");
-        ()
     }
 
-    pub async fn draw(&mut self) -> io::Result<Vec<(usize, usize, Cell)>> {
+    fn set_clipboard(&mut self, selection: &[u8], content: &[u8]) {
+        let clipboard = Clipboard {
+            selection: String::from_utf8_lossy(selection).into_owned(),
+            content: String::from_utf8_lossy(content).into_owned(),
+        };
+        debug!("clipboard: {:?}", clipboard);
+        if clipboard.is_read() && !self.allow_clipboard_read {
+            debug!("denied clipboard read");
+            return;
+        }
+        if self.clipboard_policy == ClipboardPolicy::Deny {
+            debug!("denied clipboard write");
+            return;
+        }
+        self.clipboard = Some(clipboard);
+    }
+
+    fn reply_color(&mut self, prefix: &str, color: Rgb, bell_terminated: bool) {
+        let terminator = if bell_terminated { "\x07" } else { "\x1b\\" };
+        let reply = format!("\x1b]{};{}{}", prefix, color, terminator);
+        self.replies.extend_from_slice(reply.as_bytes());
+    }
+
+    fn set_palette_color(&mut self, index: PaletteIndex, color: Option<Rgb>) {
+        debug!("set palette color {:?}: {:?}", index, color);
+        self.palette.set(index, color);
+        self.palette_changes.push(PaletteChange { index, color });
+    }
+
+    fn set_title(&mut self, title: &[&[u8]]) {
+        // the title is shown on the host screen, it must not carry escape sequences.
+        let title: String = String::from_utf8_lossy(&title.join(&b';'))
+            .chars()
+            .filter(|c| !c.is_control())
+            .collect();
+        debug!("title: {:?}", title);
+        self.title = Some(title).filter(|title| !title.is_empty());
+    }
+
+    /// OSC 4, with a list of `index;spec` pairs, where the spec is a color or `?` for a query.
+    fn osc_palette(&mut self, params: &[&[u8]], bell_terminated: bool) {
+        for pair in params.chunks(2) {
+            let (number, spec) = match (pair.first().and_then(|i| parse_number(i)), pair.get(1)) {
+                (Some(number), Some(spec)) => (number, spec),
+                _ => {
+                    debug!("invalid palette pair: {:?}", pair);
+                    return;
+                }
+            };
+            let index = PaletteIndex::Indexed(number);
+            if *spec == b"?" {
+                let color = self.palette.get(index);
+                self.reply_color(&format!("4;{}", number), color, bell_terminated);
+            } else if let Some(color) = Rgb::parse(spec) {
+                self.set_palette_color(index, Some(color));
+            }
+        }
+    }
+
+    /// OSC 10, 11 and 12. Each additional parameter applies to the next dynamic color.
+    fn osc_dynamic_color(&mut self, first: usize, specs: &[&[u8]], bell_terminated: bool) {
+        for (index, spec) in PaletteIndex::DYNAMIC.iter().skip(first).zip(specs) {
+            let osc = index.osc().unwrap_or_default();
+            if *spec == b"?" {
+                let color = self.palette.get(*index);
+                self.reply_color(&osc.to_string(), color, bell_terminated);
+            } else if let Some(color) = Rgb::parse(spec) {
+                self.set_palette_color(*index, Some(color));
+            }
+        }
+    }
+
+    /// OSC 104, resets the given palette entries, or all of them.
+    fn osc_reset_palette(&mut self, indexes: &[&[u8]]) {
+        if indexes.is_empty() {
+            (0..=255).for_each(|i| self.set_palette_color(PaletteIndex::Indexed(i), None));
+        } else {
+            for index in indexes.iter().filter_map(|i| parse_number(i)) {
+                self.set_palette_color(PaletteIndex::Indexed(index), None);
+            }
+        }
+    }
+
+    /// OSC 133 shell integration marks.
+    fn shell_mark(&mut self, mark: &[u8], params: &[&[u8]]) {
+        let line = self.line();
+        debug!("shell mark {:?} at line {}", mark, line);
+        match mark {
+            b"A" => {
+                self.command = Some(CommandEvent {
+                    prompt_line: line,
+                    cwd: self.cwd.clone(),
+                    ..Default::default()
+                });
+                self.command_input = None;
+            }
+            b"B" => self.command_input = Some((line, self.col())),
+            b"C" => {
+                let input = self.read_command_input();
+                let cwd = self.cwd.clone();
+                let command = self.command.get_or_insert_with(|| CommandEvent {
+                    prompt_line: line,
+                    cwd,
+                    ..Default::default()
+                });
+                command.output_line = Some(line);
+                command.command = input;
+            }
+            b"D" => {
+                if let Some(mut command) = self.command.take() {
+                    // an empty command line, nothing was run.
+                    if command.output_line.is_none() && command.command.is_empty() {
+                        return;
+                    }
+                    command.end_line = line;
+                    command.exit_code = params
+                        .first()
+                        .and_then(|code| std::str::from_utf8(code).ok()?.parse().ok());
+                    let first_line = self.scrollback.lines().start;
+                    self.history.retain(|command| command.end_line >= first_line);
+                    self.history.push(command.clone());
+                    self.commands.push(command);
+                }
+            }
+            _ => debug!("unhandled shell mark: {:?}", mark),
+        }
+    }
+
+    /// Reads the text between the start of the command input and the cursor.
+    fn read_command_input(&self) -> String {
+        let (line, col) = match self.command_input {
+            Some(position) => position,
+            None => return String::new(),
+        };
+        let end = self.line();
+        if line > end {
+            return String::new();
+        }
+        let (first, rows) = self.lines(line, end - line + 1);
+        // the start of the input was dropped from the scrollback.
+        if first != line {
+            return String::new();
+        }
+        let mut command = String::new();
+        for (i, row) in rows.iter().enumerate() {
+            let from = if i == 0 { col } else { 0 };
+            let to = if i + 1 == rows.len() { self.col() } else { row.len() };
+            let to = std::cmp::min(to, row.len());
+            if from >= to {
+                continue;
+            }
+            let text = row[from..to].iter().map(|cell| cell.symbol).collect::<String>();
+            command.push_str(text.trim_end());
+        }
+        command.trim().to_string()
+    }
+
+    /// Draws cells around the grid on the host screen, in host coordinates, leaving the cursor
+    /// where it was.
+    ///
+    /// The viewport is the one of the last draw, which the cursor may have left since: it is then
+    /// put on the closest cell of the viewport until the next draw.
+    pub async fn draw_around(&mut self, cells: Vec<(usize, usize, Cell)>) -> io::Result<()> {
+        let (screen, (vx, vy)) = self.visible();
+        let col = self.clamped_col().saturating_sub(vx).min(screen.width.saturating_sub(1));
+        let row = self.c_row.saturating_sub(vy).min(screen.height.saturating_sub(1));
         self.backend.hide_cursor().await?;
-        let cells = self.buffer.diff().collect::<Vec<_>>();
-        self.backend.draw(cells.iter().cloned()).await?;
+        self.backend.draw(cells.into_iter()).await?;
+        self.backend.cursor_goto(col + screen.x, row + screen.y).await?;
+        self.backend.show_cursor().await?;
+        self.backend.flush().await
+    }
+
+    /// Makes the next draw draw the whole grid, e.g after the host screen was cleared.
+    pub fn invalidate(&mut self) {
+        self.invalidated = true;
+    }
+
+    /// Returns a frame that draws the whole grid and palette, for viewers that don't have the
+    /// previous frames.
+    ///
+    /// The grid is the one of the last draw: the next frame holds the scrolls and changes since,
+    /// which would be applied twice if the keyframe already had them.
+    pub fn keyframe(&self) -> Frame {
+        let cells = (0..self.height())
+            .flat_map(|y| {
+                self.buffer
+                    .drawn_row(y)
+                    .iter()
+                    .enumerate()
+                    .map(move |(x, &cell)| (x, y, cell))
+            })
+            .collect();
+        Frame {
+            cells,
+            palette: self.palette.changes(),
+            ..Frame::default()
+        }
+    }
+
+    /// Returns the absolute lines on the screen.
+    pub fn screen_lines(&self) -> Range<usize> {
+        self.scrolled_lines..self.scrolled_lines + self.height()
+    }
+
+    /// Returns up to `count` rows from the absolute line `start`, from the scrollback and the
+    /// screen, along with the line of the first one, which is later than `start` if it was
+    /// dropped from the scrollback.
+    pub fn lines(&self, start: usize, count: usize) -> (usize, Vec<Vec<Cell>>) {
+        let start = start.max(self.scrollback.lines().start);
+        let end = start.saturating_add(count).min(self.screen_lines().end);
+        let rows = (start..end)
+            .map(|line| match line.checked_sub(self.scrolled_lines) {
+                Some(y) => self.buffer.row(y).to_vec(),
+                None => self.scrollback.get(line).unwrap_or_default().to_vec(),
+            })
+            .collect();
+        (start, rows)
+    }
+
+    /// Returns the commands that finished, whose output can still be read with `lines`.
+    pub fn history(&self) -> &[CommandEvent] {
+        &self.history
+    }
+
+    /// Returns the visible cells of the grid `rows`, in host coordinates.
+    fn host_cells(&self, rows: impl Iterator<Item = usize>) -> Vec<(usize, usize, Cell)> {
+        let (screen, (vx, vy)) = self.visible();
+        let (x, y, width) = (screen.x, screen.y, screen.width);
+        rows.flat_map(|cy| {
+            let row = self.buffer.row(cy);
+            (vx..vx + width).map(move |cx| (cx - vx + x, cy - vy + y, row[cx]))
+        })
+        .collect()
+    }
+
+    /// Moves the viewport, if any, so that the cursor is in it. Returns whether it moved.
+    fn follow_cursor(&mut self) -> bool {
+        let (col, row) = (self.clamped_col(), self.row());
+        let v = match self.viewport {
+            Some(ref mut v) => v,
+            None => return false,
+        };
+        let (x, y) = (v.x, v.y);
+        v.x = v.x.min(col).max((col + 1).saturating_sub(v.width));
+        v.y = v.y.min(row).max((row + 1).saturating_sub(v.height));
+        (x, y) != (v.x, v.y)
+    }
+
+    pub async fn draw(&mut self) -> io::Result<Frame> {
+        self.backend.hide_cursor().await?;
+        let (scrolls, cells) = self.buffer.diff();
+        let moved = self.follow_cursor();
+        let (screen, (vx, vy)) = self.visible();
+        let (x, y) = (screen.x, screen.y);
+        // scrolls of rows that are not all visible can't be done on the host, the whole
+        // viewport is drawn again instead.
+        let redraw = std::mem::take(&mut self.invalidated)
+            || moved
+            || scrolls
+                .iter()
+                .any(|s| s.top < vy || s.bottom > vy + screen.height);
+        if redraw {
+            let visible = self.host_cells(vy..vy + screen.height);
+            self.backend.draw(visible.into_iter()).await?;
+        } else {
+            // the host terminal scrolls whole rows of its screen, so it can only scroll the cast
+            // when there is nothing on its sides. Otherwise the rows that moved are drawn again.
+            let mut moved_rows = vec![false; self.height()];
+            if x == 0 && self.host_width == Some(screen.width) {
+                for scroll in &scrolls {
+                    self.backend
+                        .scroll(scroll.top - vy + y, scroll.bottom - vy + y, scroll.n)
+                        .await?;
+                }
+            } else {
+                for scroll in &scrolls {
+                    moved_rows[scroll.top..scroll.bottom].iter_mut().for_each(|m| *m = true);
+                }
+            }
+            let rows = self.host_cells((vy..vy + screen.height).filter(|&cy| moved_rows[cy]));
+            self.backend.draw(rows.into_iter()).await?;
+            let visible = |&&(cx, cy, _): &&(usize, usize, Cell)| {
+                (vx..vx + screen.width).contains(&cx)
+                    && (vy..vy + screen.height).contains(&cy)
+                    && !moved_rows[cy]
+            };
+            self.backend
+                .draw(
+                    cells
+                        .iter()
+                        .filter(visible)
+                        .map(|&(cx, cy, cell)| (cx - vx + x, cy - vy + y, cell)),
+                )
+                .await?;
+        }
+        let mut palette = std::mem::take(&mut self.palette_changes);
+        for change in &mut palette {
+            self.backend.set_palette(*change).await?;
+            // the viewers reset the entry to the host's color, not to their own.
+            if change.color.is_none() {
+                change.color = self.palette.viewer_color(change.index);
+            }
+        }
+        let mut clipboard = self.clipboard.take();
+        if let Some(ref c) = clipboard {
+            self.backend.set_clipboard(&c.selection, &c.content).await?;
+        }
+        // reads are answered by the host terminal through stdin, viewers never see them.
+        if self.clipboard_policy != ClipboardPolicy::Share
+            || clipboard.as_ref().is_some_and(Clipboard::is_read)
+        {
+            clipboard = None;
+        }
         self.backend
-            .cursor_goto(self.c_col + self.rect.x, self.c_row + self.rect.y)
+            .cursor_goto(self.clamped_col() - vx + x, self.c_row - vy + y)
             .await?;
         self.backend.show_cursor().await?;
         self.backend.flush().await?;
-        Ok(cells)
+        Ok(Frame {
+            scrolls,
+            cells,
+            clipboard,
+            palette,
+            commands: std::mem::take(&mut self.commands),
+        })
     }
 }
 
@@ -404,8 +890,23 @@ impl<B: Backend> vte::Perform for Terminal<B> {
 
     // TODO replace OSC parsing with parser combinators.
     #[inline]
-    fn osc_dispatch(&mut self, params: &[&[u8]], _bell_terminated: bool) {
-        match params[0] {
+    fn osc_dispatch(&mut self, params: &[&[u8]], bell_terminated: bool) {
+        match params {
+            [b"0", title @ ..] | [b"2", title @ ..] => self.set_title(title),
+            [b"4", pairs @ ..] => self.osc_palette(pairs, bell_terminated),
+            [b"7", url, ..] => {
+                self.cwd = shell::parse_cwd(url);
+                debug!("working directory: {:?}", self.cwd);
+            }
+            [b"10", specs @ ..] => self.osc_dynamic_color(0, specs, bell_terminated),
+            [b"11", specs @ ..] => self.osc_dynamic_color(1, specs, bell_terminated),
+            [b"12", specs @ ..] => self.osc_dynamic_color(2, specs, bell_terminated),
+            [b"52", selection, content] => self.set_clipboard(selection, content),
+            [b"104", indexes @ ..] => self.osc_reset_palette(indexes),
+            [b"133", mark, params @ ..] => self.shell_mark(mark, params),
+            [b"110", ..] => self.set_palette_color(PaletteIndex::Foreground, None),
+            [b"111", ..] => self.set_palette_color(PaletteIndex::Background, None),
+            [b"112", ..] => self.set_palette_color(PaletteIndex::Cursor, None),
             _ => debug!("[unhandled osc dispatch] byte={:?}", params),
         }
     }
@@ -419,7 +920,7 @@ impl<B: Backend> vte::Perform for Terminal<B> {
         _has_ignored_intermediates: bool,
         action: char,
     ) {
-        let mut params_iter = params.iter();
+        let mut params_iter = iter_params(params);
         let mut next_param_or = |default: usize| {
             params_iter
                 .next()
@@ -428,7 +929,7 @@ impl<B: Backend> vte::Perform for Terminal<B> {
                 .unwrap_or(default)
         };
 
-        match (action, intermediates.get(0)) {
+        match (action, intermediates.first()) {
             ('A', None) => self.move_up(next_param_or(1)),
             ('B', None) | ('e', None) => self.move_down(next_param_or(1)),
             ('C', None) | ('a', None) => self.move_forward(next_param_or(1)),
@@ -453,6 +954,12 @@ impl<B: Backend> vte::Perform for Terminal<B> {
                 self.clear_screen(mode);
             }
             ('L', None) => self.insert_line(next_param_or(1)),
+            ('S', None) => self
+                .buffer
+                .scroll_up(self.scroll_range.clone(), next_param_or(1)),
+            ('T', None) => self
+                .buffer
+                .scroll_down(self.scroll_range.clone(), next_param_or(1)),
             ('K', None) => {
                 let mode = match next_param_or(0) {
                     0 => LineClearMode::Right,
@@ -465,67 +972,16 @@ impl<B: Backend> vte::Perform for Terminal<B> {
                 self.clear_line(mode);
             }
             ('M', None) => self.delete_lines(next_param_or(1)),
-            //colors
-            ('m', None) => {
-                //println!("params: {:?}, intermediates: {:?}", params, intermediates);
-                let params = params.iter().map(|p| p[0] as usize).collect::<Vec<_>>();
-                match params[0] {
-                    0 => {
-                        self.c_style.reset();
-                    }
-                    3 => {
-                        self.c_style.set_italic();
-                    }
-                    1 => {
-                        self.c_style.set_bold();
-                        if params.len() == 2 {
-                            self.c_style.set_color_3bits(params[1] as usize);
-                        }
-                    }
-                    23 => {
-                        self.c_style.unset_italic();
-                    }
-                    24 => {
-                        self.c_style.unset_underline();
-                    }
-                    // set foreground
-                    38 => match params[1] {
-                        5 => self.c_style.fg = crate::style::Color::Indexed(params[2] as u8),
-                        2 => {
-                            self.c_style.fg = crate::style::Color::Rgb(
-                                params[2] as u8,
-                                params[3] as u8,
-                                params[4] as u8,
-                            )
-                        }
-                        _ => unreachable!("bad rgb color"),
-                    },
-                    39 => {
-                        self.c_style.fg = crate::style::Color::default();
-                    }
-                    // set background
-                    48 => match params[1] {
-                        5 => self.c_style.bg = crate::style::Color::Indexed(params[2] as u8),
-                        2 => {
-                            self.c_style.bg = crate::style::Color::Rgb(
-                                params[2] as u8,
-                                params[3] as u8,
-                                params[4] as u8,
-                            )
-                        }
-                        _ => unreachable!("bad rgb color"),
-                    },
-                    49 => {
-                        self.c_style.bg = crate::style::Color::default();
-                    }
-                    90..=97 | 100..=107 | 30..=37 | 40..=47 => {
-                        self.c_style.set_color_3bits(params[0] as usize)
-                    }
-                    value => unimplemented!("unimplemented color: {}", value),
-                }
+            ('h', Some(b'?')) => iter_params(params)
+                .for_each(|param| self.set_private_mode(param[0], true)),
+            ('l', Some(b'?')) => iter_params(params)
+                .for_each(|param| self.set_private_mode(param[0], false)),
+            ('p', Some(b'?')) if intermediates.get(1) == Some(&b'$') => {
+                self.report_private_mode(next_param_or(0) as u16)
             }
+            ('m', None) => self.set_graphic_rendition(params),
             ('r', None) => {
-                let top = next_param_or(1) as usize;
+                let top = next_param_or(1);
                 let bottom = params_iter
                     .next()
                     .map(|param| param[0] as usize)
@@ -535,14 +991,14 @@ impl<B: Backend> vte::Perform for Terminal<B> {
             }
             (c, intermediates) => debug!(
                 "[unhandled csi dispatch] char={}, intermediates={:?}",
-                c as char, intermediates
+                c, intermediates
             ),
         }
     }
 
     #[inline]
     fn esc_dispatch(&mut self, intermediates: &[u8], _ignore: bool, byte: u8) {
-        match (byte, intermediates.get(0)) {
+        match (byte, intermediates.first()) {
             //(b'B', intermediate) => configure_charset!(StandardCharset::Ascii, intermediate),
             (b'D', None) => self.linefeed(),
             (b'E', None) => {
@@ -571,6 +1027,35 @@ impl<B: Backend> vte::Perform for Terminal<B> {
     }
 }
 
+/// Iterates over the parameters and their subparameters, never empty. vte yields empty ones
+/// forever when the last parameter's subparameters didn't fit, so the rest is dropped.
+fn iter_params(params: &vte::Params) -> impl Iterator<Item = &[u16]> {
+    params.iter().take_while(|param| !param.is_empty())
+}
+
+/// Parses the parameters following a 38 or 48 SGR: `5;index` or `2;r;g;b`.
+fn parse_extended_color(params: &mut dyn Iterator<Item = u16>) -> Option<Color> {
+    let mut next = || params.next().map(|p| std::cmp::min(p, 255) as u8);
+    match next()? {
+        5 => Some(Color::Indexed(next()?)),
+        2 => Some(Color::Rgb(next()?, next()?, next()?)),
+        _ => None,
+    }
+}
+
+/// The colon form of direct colors may have a color space id: `38:2:id:r:g:b`, which is skipped.
+fn colon_color_params(sub: &[u16]) -> impl Iterator<Item = u16> + '_ {
+    let skip_color_space = sub.len() == 5 && sub[0] == 2;
+    sub.iter()
+        .enumerate()
+        .filter(move |(i, _)| !(skip_color_space && *i == 1))
+        .map(|(_, p)| *p)
+}
+
+fn parse_number(param: &[u8]) -> Option<u8> {
+    std::str::from_utf8(param).ok()?.parse().ok()
+}
+
 // from allacritty
 #[allow(non_snake_case, dead_code)]
 pub mod C0 {
@@ -641,3 +1126,348 @@ pub mod C0 {
     /// Delete, should be ignored by terminal.
     pub const DEL: u8 = 0x7f;
 }
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::backends::TermionBackend;
+
+    fn terminal(width: usize, height: usize) -> Terminal<TermionBackend<Vec<u8>>> {
+        Terminal::new(Rect::new(0, 0, width, height), TermionBackend::new(Vec::new()))
+    }
+
+    fn feed<B: Backend>(terminal: &mut Terminal<B>, bytes: &[u8]) {
+        let mut parser = vte::Parser::new();
+        for &byte in bytes {
+            parser.advance(terminal, byte);
+        }
+    }
+
+    #[tokio::test]
+    async fn clipboard_is_forwarded_to_the_host_only() {
+        let mut terminal = terminal(10, 3);
+        feed(&mut terminal, b"\x1b]52;c;aGVsbG8=\x07");
+        assert_eq!(terminal.clipboard.as_ref().map(|c| c.content.as_str()), Some("aGVsbG8="));
+        let frame = terminal.draw().await.unwrap();
+        assert_eq!(frame.clipboard, None);
+        assert_eq!(terminal.clipboard, None);
+    }
+
+    #[tokio::test]
+    async fn clipboard_is_shared_with_the_viewers() {
+        let mut terminal = terminal(10, 3);
+        terminal.set_clipboard_policy(ClipboardPolicy::Share, false);
+        feed(&mut terminal, b"\x1b]52;c;aGVsbG8=\x1b\\");
+        let frame = terminal.draw().await.unwrap();
+        assert_eq!(frame.clipboard.map(|c| c.content), Some("aGVsbG8=".to_string()));
+    }
+
+    #[test]
+    fn clipboard_write_is_denied() {
+        let mut terminal = terminal(10, 3);
+        terminal.set_clipboard_policy(ClipboardPolicy::Deny, true);
+        feed(&mut terminal, b"\x1b]52;c;aGVsbG8=\x07");
+        assert_eq!(terminal.clipboard, None);
+    }
+
+    #[tokio::test]
+    async fn clipboard_read_is_denied_unless_allowed() {
+        let mut terminal = terminal(10, 3);
+        terminal.set_clipboard_policy(ClipboardPolicy::Share, false);
+        feed(&mut terminal, b"\x1b]52;c;?\x07");
+        assert_eq!(terminal.clipboard, None);
+
+        terminal.set_clipboard_policy(ClipboardPolicy::Share, true);
+        feed(&mut terminal, b"\x1b]52;c;?\x07");
+        assert!(terminal.clipboard.as_ref().is_some_and(Clipboard::is_read));
+        // the host answers the read, the viewers never see it.
+        let frame = terminal.draw().await.unwrap();
+        assert_eq!(frame.clipboard, None);
+    }
+
+    #[test]
+    fn palette_answers_and_resets_to_the_host_colors() {
+        let background = Rgb(0x11, 0x22, 0x33);
+        let mut terminal = terminal(10, 3);
+        terminal.set_initial_palette(Palette::new(&[(PaletteIndex::Background, background)]));
+        feed(&mut terminal, b"\x1b]11;?\x07");
+        assert_eq!(terminal.take_replies(), b"\x1b]11;rgb:1111/2222/3333\x07");
+
+        feed(&mut terminal, b"\x1b]11;#000000\x07\x1b]111\x07\x1b]11;?\x1b\\");
+        assert_eq!(terminal.take_replies(), b"\x1b]11;rgb:1111/2222/3333\x1b\\");
+    }
+
+    #[tokio::test]
+    async fn viewers_get_the_host_colors() {
+        let background = Rgb(0x11, 0x22, 0x33);
+        let mut terminal = terminal(10, 3);
+        terminal.set_initial_palette(Palette::new(&[(PaletteIndex::Background, background)]));
+        let color = |frame: &Frame, index| {
+            frame.palette.iter().find(|change| change.index == index).and_then(|c| c.color)
+        };
+        let keyframe = terminal.keyframe();
+        assert_eq!(keyframe.palette.len(), 259);
+        assert_eq!(color(&keyframe, PaletteIndex::Background), Some(background));
+
+        feed(&mut terminal, b"\x1b]11;#000000\x07\x1b]111\x07\x1b]110\x07");
+        let frame = terminal.draw().await.unwrap();
+        assert_eq!(frame.palette[1].color, Some(background));
+        // a default the host didn't report.
+        let foreground = PaletteChange {
+            index: PaletteIndex::Foreground,
+            color: None,
+        };
+        assert_eq!(frame.palette[2], foreground);
+    }
+
+    fn text(row: &[Cell]) -> String {
+        row.iter().map(|cell| cell.symbol).collect()
+    }
+
+    #[test]
+    fn lines_from_the_scrollback_and_the_screen() {
+        let mut terminal = terminal(2, 2);
+        feed(&mut terminal, b"a\r\nb\r\nc\r\nd");
+        assert_eq!(terminal.screen_lines(), 2..4);
+        let (start, rows) = terminal.lines(1, 10);
+        assert_eq!(start, 1);
+        let rows: Vec<_> = rows.iter().map(|row| text(row)).collect();
+        assert_eq!(rows, ["b ", "c ", "d "]);
+    }
+
+    #[test]
+    fn command_read_back_from_the_scrollback() {
+        let mut terminal = terminal(4, 2);
+        feed(&mut terminal, b"\x1b]133;A\x07$ \x1b]133;B\x07echo hello\x1b]133;C\x07\r\n");
+        feed(&mut terminal, b"hello\r\n\x1b]133;D;0\x07");
+        let command = &terminal.history()[0];
+        assert_eq!(command.command, "echo hello");
+        assert_eq!(command.exit_code, Some(0));
+        let (start, rows) = terminal.lines(command.prompt_line, 1);
+        assert_eq!((start, text(&rows[0])), (0, "$ ec".to_string()));
+    }
+
+    /// A writer that can still be read once given to the backend.
+    #[derive(Clone, Default)]
+    struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl std::io::Write for Output {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Output {
+        fn take(&self) -> Vec<u8> {
+            std::mem::take(&mut self.0.borrow_mut())
+        }
+    }
+
+    #[tokio::test]
+    async fn modes_are_mirrored_on_the_host() {
+        let output = Output::default();
+        let backend = TermionBackend::new(output.clone());
+        let mut terminal = Terminal::new(Rect::new(0, 0, 10, 3), backend);
+        feed(&mut terminal, b"\x1b[?1000;1006h\x1b[?2004h\x1b[?25l");
+        assert_eq!(terminal.mode(), Mode::MOUSE_CLICK | Mode::SGR_MOUSE | Mode::BRACKETED_PASTE);
+        terminal.apply_modes().await.unwrap();
+        assert_eq!(output.take(), b"\x1b[?1000h\x1b[?1006h\x1b[?2004h");
+
+        feed(&mut terminal, b"\x1b[?1000l\x1b[?2004$p\x1b[?1000$p\x1b[?9$p");
+        terminal.apply_modes().await.unwrap();
+        assert_eq!(output.take(), b"\x1b[?1000l");
+        assert_eq!(terminal.take_replies(), b"\x1b[?2004;1$y\x1b[?1000;2$y\x1b[?9;0$y");
+        // nothing changed since.
+        terminal.apply_modes().await.unwrap();
+        assert_eq!(output.take(), b"");
+    }
+
+    #[test]
+    fn synchronized_update() {
+        let mut terminal = terminal(10, 3);
+        feed(&mut terminal, b"\x1b[?2026h\x1b[?2026$p");
+        assert!(terminal.is_synchronized());
+        feed(&mut terminal, b"\x1b[?2026l\x1b[?2026$p");
+        assert!(!terminal.is_synchronized());
+        assert_eq!(terminal.take_replies(), b"\x1b[?2026;1$y\x1b[?2026;2$y");
+    }
+
+    #[test]
+    fn synchronized_update_times_out() {
+        let mut terminal = terminal(10, 3);
+        feed(&mut terminal, b"\x1b[?2026h");
+        terminal.synchronized_since = Some(Instant::now() - SYNCHRONIZED_UPDATE_TIMEOUT);
+        assert!(!terminal.is_synchronized());
+    }
+
+    fn screen(terminal: &Terminal<TermionBackend<Vec<u8>>>) -> Vec<String> {
+        (0..terminal.height()).map(|y| text(terminal.buffer.row(y))).collect()
+    }
+
+    #[test]
+    fn tab_stops_at_the_last_column() {
+        let mut terminal = terminal(20, 2);
+        feed(&mut terminal, b"ab\t");
+        assert_eq!(terminal.col(), 8);
+        feed(&mut terminal, b"\t\t\tc");
+        assert_eq!(screen(&terminal)[0], "ab                 c");
+    }
+
+    #[test]
+    fn backspace_after_the_last_column() {
+        let mut terminal = terminal(4, 2);
+        feed(&mut terminal, b"abcd\x08X\r\x08\x08Y");
+        assert_eq!(screen(&terminal), ["YbXd", "    "]);
+    }
+
+    #[test]
+    fn scroll_region() {
+        let mut terminal = terminal(2, 4);
+        feed(&mut terminal, b"a\r\nb\r\nc\r\nd");
+        // an empty region is ignored, and a valid one moves the cursor home.
+        feed(&mut terminal, b"\x1b[3;2r\x1b[2;3r");
+        assert_eq!(terminal.scroll_range, 1..3);
+        assert_eq!((terminal.col(), terminal.row()), (0, 0));
+
+        feed(&mut terminal, b"\x1b[3;1H\n");
+        assert_eq!(screen(&terminal), ["a ", "c ", "  ", "d "]);
+        assert_eq!(terminal.screen_lines(), 0..4);
+
+        // the bottom is clamped to the screen.
+        feed(&mut terminal, b"\x1b[2;99r");
+        assert_eq!(terminal.scroll_range, 1..4);
+    }
+
+    #[test]
+    fn empty_subparameters() {
+        // vte yields empty parameters forever once there are too many subparameters.
+        let mut terminal = terminal(10, 3);
+        let colons = ":".repeat(40);
+        for sequence in ["r", "h", "m"] {
+            feed(&mut terminal, format!("\x1b[2;{}{}", colons, sequence).as_bytes());
+            feed(&mut terminal, format!("\x1b[?2;{}{}", colons, sequence).as_bytes());
+            feed(&mut terminal, format!("\x1b[38;{}{}", colons, sequence).as_bytes());
+        }
+        let crash = b"3;\x1b[2;:::::::::::::::::::::::3::::::0:::::::rgb:\xca\x8d\x1b[2208";
+        feed(&mut terminal, crash);
+    }
+
+    /// A cast of `width` x `height` at column `x` of the host screen, after a first draw.
+    async fn drawn(
+        x: usize,
+        width: usize,
+        height: usize,
+        host_width: usize,
+    ) -> (Terminal<TermionBackend<Output>>, Output) {
+        let output = Output::default();
+        let backend = TermionBackend::new(output.clone());
+        let mut terminal = Terminal::new(Rect::new(x, 0, width, height), backend);
+        terminal.set_host_width(host_width);
+        feed(&mut terminal, b"a\r\nb\r\nc");
+        terminal.draw().await.unwrap();
+        output.take();
+        (terminal, output)
+    }
+
+    #[tokio::test]
+    async fn full_width_cast_is_scrolled_on_the_host() {
+        let (mut terminal, output) = drawn(0, 4, 3, 4).await;
+        feed(&mut terminal, b"\r\nd");
+        let frame = terminal.draw().await.unwrap();
+        assert_eq!(frame.scrolls.len(), 1);
+        let output = String::from_utf8(output.take()).unwrap();
+        assert!(output.contains("\x1b[1;3r\x1b[1S\x1b[r"));
+        // only the new row is drawn.
+        assert_eq!(output.matches('d').count(), 1);
+        assert!(!output.contains('b'));
+    }
+
+    #[tokio::test]
+    async fn narrower_cast_draws_the_moved_rows() {
+        for (x, host_width) in [(0, 10), (3, 7)] {
+            let (mut terminal, output) = drawn(x, 4, 3, host_width).await;
+            feed(&mut terminal, b"\r\nd");
+            let frame = terminal.draw().await.unwrap();
+            // the viewers still get the scroll.
+            assert_eq!(frame.scrolls.len(), 1);
+            let output = String::from_utf8(output.take()).unwrap();
+            assert!(!output.contains("S\x1b[r"));
+            assert!(output.contains('b') && output.contains('c') && output.contains('d'));
+        }
+    }
+
+    #[tokio::test]
+    async fn viewport_follows_the_cursor() {
+        let output = Output::default();
+        let backend = TermionBackend::new(output.clone());
+        let mut terminal = Terminal::new(Rect::new(0, 0, 10, 5), backend);
+        terminal.set_viewport(4, 2);
+        feed(&mut terminal, b"\x1b[4;6Habcd");
+        terminal.draw().await.unwrap();
+        let (screen, origin) = terminal.visible();
+        assert_eq!((screen.width, screen.height), (4, 2));
+        // the cursor is after `d`, in the last column of the viewport.
+        assert_eq!(origin, (6, 2));
+        let output = String::from_utf8(output.take()).unwrap();
+        assert!(output.contains('b') && output.contains('c') && output.contains('d'));
+        assert!(!output.contains('a'));
+    }
+
+    #[tokio::test]
+    async fn cursor_left_of_the_viewport_before_the_draw() {
+        let output = Output::default();
+        let backend = TermionBackend::new(output.clone());
+        let mut terminal = Terminal::new(Rect::new(0, 0, 10, 5), backend);
+        terminal.set_viewport(4, 2);
+        feed(&mut terminal, b"\x1b[4;6Habcd");
+        terminal.draw().await.unwrap();
+        output.take();
+        // the status line is drawn before the viewport follows the cursor.
+        feed(&mut terminal, b"\r\x1b[A");
+        terminal.draw_around(Vec::new()).await.unwrap();
+        let output = String::from_utf8(output.take()).unwrap();
+        assert!(output.contains("\x1b[1;1H"));
+    }
+
+    #[tokio::test]
+    async fn keyframe_then_the_next_frame() {
+        // what a viewer that joins has on its screen.
+        fn apply(screen: &mut [Vec<char>], frame: &Frame) {
+            for scroll in &frame.scrolls {
+                let region = &mut screen[scroll.top..scroll.bottom];
+                let n = scroll.n.unsigned_abs();
+                let exposed = if scroll.n > 0 {
+                    region.rotate_left(n);
+                    region.len() - n..region.len()
+                } else {
+                    region.rotate_right(n);
+                    0..n
+                };
+                region[exposed].iter_mut().for_each(|row| row.fill(' '));
+            }
+            for &(x, y, cell) in &frame.cells {
+                screen[y][x] = cell.symbol;
+            }
+        }
+
+        let mut terminal = terminal(4, 3);
+        feed(&mut terminal, b"a\r\nb\r\nc");
+        terminal.draw().await.unwrap();
+        // scrolled, but not drawn yet.
+        feed(&mut terminal, b"\r\nd");
+        let mut viewer = vec![vec![' '; 4]; 3];
+        apply(&mut viewer, &terminal.keyframe());
+        apply(&mut viewer, &terminal.draw().await.unwrap());
+        let viewer: Vec<String> = viewer.iter().map(|row| row.iter().collect()).collect();
+        assert_eq!(viewer, screen(&terminal));
+    }
+}
+
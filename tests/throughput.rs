@@ -0,0 +1,105 @@
+//! Pipes a megabyte through a cast: it is typed on the host's terminal, goes through the cast to
+//! the shell's pty, and is echoed back to the cast and drawn on the host's screen.
+
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::os::unix::io::FromRawFd;
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use nix::libc;
+use nix::pty::{openpty, Winsize};
+use nix::unistd::dup;
+
+/// Lines typed, each short enough for the pty's line discipline.
+const LINE: &[u8] = &[b'x'; 99];
+const LINES: usize = 10_000;
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+#[test]
+fn a_megabyte_through_the_cast() {
+    let size = Winsize {
+        ws_row: 50,
+        ws_col: 120,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    let pty = openpty(Some(&size), None).unwrap();
+    let stdio = || unsafe { Stdio::from_raw_fd(dup(pty.slave).unwrap()) };
+    let mut child = unsafe {
+        Command::new(env!("CARGO_BIN_EXE_termcast"))
+            .args(["cast", "--listen", "127.0.0.1:0"])
+            .stdin(stdio())
+            .stdout(stdio())
+            .stderr(stdio())
+            .pre_exec(|| {
+                if libc::setsid() < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                // makes the pty the controlling terminal, for the cast's resize signals.
+                if libc::ioctl(0, libc::TIOCSCTTY as _, 0) < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            })
+            .spawn()
+            .unwrap()
+    };
+    nix::unistd::close(pty.slave).unwrap();
+    let mut master = unsafe { File::from_raw_fd(pty.master) };
+
+    // the host's screen is read as it is drawn, or the cast would block writing to it.
+    let mut reader = master.try_clone().unwrap();
+    let (started, started_receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buf = vec![0; 64 * 1024];
+        let mut started = Some(started);
+        let mut seen = Vec::new();
+        while let Ok(n) = reader.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+            // what is typed before the alternate screen is taken as an answer to the palette
+            // queries.
+            if let Some(ref sender) = started {
+                seen.extend_from_slice(&buf[..n]);
+                if seen.windows(8).any(|w| w == b"\x1b[?1049h") {
+                    let _ = sender.send(());
+                    started = None;
+                }
+            }
+        }
+    });
+    started_receiver
+        .recv_timeout(TIMEOUT).expect("the cast didn't start");
+
+    let count = std::env::temp_dir().join(format!("termcast-throughput-{}", std::process::id()));
+    let start = Instant::now();
+    write!(master, "wc -c > {}\r", count.display()).unwrap();
+    for _ in 0..LINES {
+        master.write_all(LINE).unwrap();
+        master.write_all(b"\r").unwrap();
+    }
+    // end of file for `wc`, then the shell exits and the cast with it.
+    master.write_all(b"\x04exit\r").unwrap();
+
+    let status = loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            break status;
+        }
+        if start.elapsed() > TIMEOUT {
+            let _ = child.kill();
+            panic!("the cast didn't exit");
+        }
+        thread::sleep(Duration::from_millis(50));
+    };
+    let elapsed = start.elapsed();
+    let counted = fs::read_to_string(&count).unwrap();
+    let _ = fs::remove_file(&count);
+    assert!(status.success());
+    assert_eq!(counted.trim(), (LINES * (LINE.len() + 1)).to_string());
+    println!("1 MB through the cast in {:?}", elapsed);
+}